@@ -0,0 +1,70 @@
+// Copyright 2024 (c) Nathaniel Clark
+
+use musicctl::{DisplayOrder, McError, PluginKind};
+use serde::Deserialize;
+use std::{collections::HashMap, path::PathBuf};
+
+/// Contents of `$XDG_CONFIG_HOME/musicctl/config.toml`
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub(crate) struct Config {
+    /// Short names for commands, e.g. `p = "play"`
+    pub aliases: HashMap<String, String>,
+    pub focus: Focus,
+    pub display_order: Option<DisplayOrder>,
+    /// Only use players whose track has an artist, which skips most radio
+    /// streams and podcasts
+    pub require_artist: bool,
+    /// Player names (or parts of them) to prefer, most preferred first
+    pub priority: Vec<String>,
+    /// Bus names (or parts of them) of players to never use
+    pub ignore: Vec<String>,
+    /// MPD server (`host` or `host:port`), overridden by `MUSICCTL_MPD_HOST`
+    pub mpd_host: Option<String>,
+    /// Route extra bus names to an existing plugin
+    #[serde(rename = "service")]
+    pub services: Vec<Service>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct Service {
+    pub name: String,
+    pub kind: PluginKind,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub(crate) struct Focus {
+    /// Shell command printing the focused application's id or PID
+    pub command: Option<String>,
+}
+
+fn config_path() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|x| !x.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|x| PathBuf::from(x).join(".config")))
+        .map(|x| x.join(env!("CARGO_PKG_NAME")).join("config.toml"))
+}
+
+impl Config {
+    /// Bus name to plugin mapping from `[[service]]` entries
+    pub(crate) fn service_map(&self) -> HashMap<String, PluginKind> {
+        self.services
+            .iter()
+            .map(|x| (x.name.clone(), x.kind))
+            .collect()
+    }
+
+    /// Load the user's config, a missing file is the default config
+    pub(crate) fn load() -> Result<Self, McError> {
+        let Some(path) = config_path() else {
+            return Ok(Config::default());
+        };
+        match std::fs::read_to_string(path) {
+            Ok(s) => Ok(toml::from_str(&s)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
@@ -0,0 +1,104 @@
+// Copyright 2024 (c) Nathaniel Clark
+
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::{Duration, SystemTime},
+};
+
+/// Covers not shown for this long are removed from the cache
+const MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+fn cache_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CACHE_HOME")
+        .filter(|x| !x.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|x| PathBuf::from(x).join(".cache")))
+        .map(|x| x.join(env!("CARGO_PKG_NAME")).join("covers"))
+}
+
+/// FNV-1a hash of `s`, which unlike `DefaultHasher` stays the same across
+/// Rust releases, so cached covers keep their names
+fn fnv1a(s: &str) -> u64 {
+    s.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Remove cached covers last used more than `MAX_AGE` ago
+async fn prune(dir: &Path) {
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let stale = entry
+            .metadata()
+            .await
+            .and_then(|x| x.modified())
+            .ok()
+            .and_then(|x| x.elapsed().ok())
+            .is_some_and(|x| x > MAX_AGE);
+        if stale {
+            let _ = tokio::fs::remove_file(entry.path()).await;
+        }
+    }
+}
+
+/// Download `url` into `path` with curl, removing any partial file on failure
+async fn download(url: &str, path: &PathBuf) -> bool {
+    let part = path.with_extension("part");
+    let ok = tokio::process::Command::new("curl")
+        .args(["--silent", "--fail", "--location", "--max-time", "5"])
+        .arg("--output")
+        .arg(&part)
+        .arg(url)
+        .stdin(Stdio::null())
+        .status()
+        .await
+        .is_ok_and(|x| x.success())
+        && tokio::fs::rename(&part, path).await.is_ok();
+    if !ok {
+        let _ = tokio::fs::remove_file(&part).await;
+    }
+    ok
+}
+
+/// Icon usable by the notification daemon for cover `url`
+///
+/// Remote covers are downloaded once into the cache, keyed by URL; if that
+/// fails the URL is returned unchanged. Each use marks the cached file as
+/// recent, and covers unused for `MAX_AGE` are pruned on the next download.
+pub(crate) async fn local_cover(url: &str) -> String {
+    if !(url.starts_with("http://") || url.starts_with("https://")) {
+        return url.to_string();
+    }
+    let Some(dir) = cache_dir() else {
+        return url.to_string();
+    };
+    let path = dir.join(format!("{:016x}", fnv1a(url)));
+
+    if path.exists() {
+        // Mark as recently used so pruning keeps it
+        let _ = std::fs::File::options()
+            .append(true)
+            .open(&path)
+            .and_then(|x| x.set_modified(SystemTime::now()));
+    } else {
+        prune(&dir).await;
+        if !(tokio::fs::create_dir_all(&dir).await.is_ok() && download(url, &path).await) {
+            return url.to_string();
+        }
+    }
+    path.to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fnv1a_known_values() {
+        assert_eq!(fnv1a(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a("a"), 0xaf63_dc4c_8601_ec8c);
+    }
+}
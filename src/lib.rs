@@ -0,0 +1,21 @@
+// Copyright 2024 (c) Nathaniel Clark
+
+//! Discover and control music players over D-Bus
+
+mod plugin;
+
+pub use crate::plugin::{
+    first_active, format_duration, get_all, get_connection_pid, get_name_owner, prune,
+    Capabilities, DisplayOrder, LoopStatus, McError, MusicCtl, MusicInfo, PlaybackStatus,
+    PluginKind,
+};
+
+/// D-Bus proxies for the supported player interfaces
+pub mod proxy {
+    pub use crate::plugin::{
+        mpris::{MediaPlayer2Proxy, Mpris2Proxy, TrackListProxy},
+        radiotray::RadioTrayNGProxy,
+        shairportsync::ShairportSyncProxy,
+        DBusProxy,
+    };
+}
@@ -1,39 +1,478 @@
 // Copyright 2023 (c) Nathaniel Clark
 
-mod plugin;
+mod ascii;
+mod config;
+mod cover;
+mod template;
 
-use crate::plugin::{get_all, McError, MusicCtl};
+use crate::ascii::to_ascii;
+use crate::config::Config;
+use musicctl::{
+    first_active, format_duration, get_all, get_connection_pid, get_name_owner, prune,
+    DisplayOrder, LoopStatus, McError, MusicCtl, MusicInfo, PlaybackStatus, PluginKind,
+};
 
-use clap::{Parser, ValueEnum};
-use futures::future::join_all;
-use std::{collections::HashMap, process::ExitCode};
-use zbus::{zvariant::Value, Connection};
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use futures::{future::join_all, StreamExt};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::OsString,
+    io::Write,
+    path::PathBuf,
+    process::ExitCode,
+    time::Duration,
+};
+use thiserror::Error;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::debug;
+use tracing_subscriber::{filter::Targets, layer::SubscriberExt, util::SubscriberInitExt};
+use zbus::{fdo, zvariant::Value, Connection};
 use zbus_macros::proxy;
 
 /// Looks for running music player and issues appropriate command to it
 #[derive(Debug, Default, Clone, Parser)]
+#[clap(after_help = "Exit status: 0 success, 1 error, 3 no active player, \
+                  4 not supported by the player, 5 player did not answer in time")]
 struct App {
-    #[clap(long, short)]
+    /// Log player discovery and calls to stderr
+    #[clap(long, short, global = true)]
     debug: bool,
 
-    #[clap(long, short)]
+    /// Use the player whose name contains INSTANCE (ignoring case); an exact
+    /// name wins over a partial one, then a playing player over the rest
+    #[clap(long, short, global = true)]
     instance: Option<String>,
 
-    #[clap(value_enum, default_value_t)]
-    command: Command,
+    /// Never use players whose bus name contains IGNORE (ignoring case),
+    /// in addition to `ignore` in config
+    #[clap(long, global = true)]
+    ignore: Vec<String>,
+
+    /// Give up on a player not answering within MS milliseconds
+    #[clap(long, global = true, value_name = "MS", default_value_t = 2000)]
+    timeout_ms: u64,
+
+    /// Look for players on the system bus instead of the session bus
+    #[clap(long, global = true)]
+    system: bool,
+
+    /// Skip players that no longer answer on the bus
+    #[clap(long, global = true)]
+    prune: bool,
+
+    /// Send transport command (play, pause, next, ...) to every active player
+    #[clap(long, global = true, conflicts_with_all = ["instance", "focused"])]
+    all: bool,
+
+    /// Select the player of the focused window (see `[focus]` in config)
+    #[clap(long, global = true, conflicts_with = "instance")]
+    focused: bool,
+
+    /// Select the player shown as [N] by list
+    #[clap(long, global = true, value_name = "N", conflicts_with_all = ["instance", "focused", "all"])]
+    index: Option<usize>,
+
+    /// Print info and list as JSON
+    #[clap(long, global = true)]
+    json: bool,
+
+    /// Print info as JSON for a Waybar custom module
+    #[clap(long, global = true, conflicts_with = "json")]
+    waybar: bool,
+
+    /// Template for info output, e.g. "{artist} - {title}"
+    /// (placeholders: {artist} {title} {album} {genre} {year} {track} {disc}
+    /// {progress} {player})
+    #[clap(long, global = true)]
+    format: Option<String>,
+
+    /// Order of track fields in output [default: album-title-artist]
+    #[clap(long, global = true, value_enum)]
+    display_order: Option<Order>,
+
+    /// Separator between player name and track in list output
+    #[clap(long, global = true, default_value = ": ")]
+    separator: String,
+
+    /// Append "(MPRIS)" to names of MPRIS players
+    #[clap(long, global = true)]
+    show_plugin: bool,
+
+    /// Transliterate output to plain ASCII
+    #[clap(long, global = true)]
+    ascii: bool,
+
+    /// Show position and length of current track
+    #[clap(long, global = true)]
+    show_time: bool,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
 }
 
-#[derive(Debug, Default, Clone, Copy, ValueEnum)]
+impl App {
+    /// Name of `player` for output
+    async fn player_name(&self, player: &dyn MusicCtl) -> Result<String, McError> {
+        let name = player.mc_name().await?;
+        Ok(match player.mc_kind() {
+            PluginKind::Mpris if self.show_plugin => format!("{name} (MPRIS)"),
+            _ => name,
+        })
+    }
+
+    /// Current track of `player` formatted per the output options
+    async fn track_line(
+        &self,
+        player: &dyn MusicCtl,
+        order: DisplayOrder,
+        extras: &Extras,
+    ) -> Result<Option<String>, McError> {
+        let Some(mut info) = player.mc_info().await? else {
+            return Ok(None);
+        };
+        let position = match self.show_time || extras.progress {
+            true => match player.mc_position().await {
+                Err(McError::Unsupported(_)) => None,
+                x => Some(x?),
+            },
+            false => None,
+        };
+        if self.show_time {
+            info.position = position;
+        }
+        let progress = match position.filter(|_| extras.progress) {
+            Some(x) => progress_bar(x, info.length, extras.progress_width),
+            None => String::new(),
+        };
+        let name = self.player_name(player).await?;
+        if let Some(format) = &self.format {
+            let number = |x: Option<u32>| x.map(|x| x.to_string()).unwrap_or_default();
+            let (year, track, disc) = (
+                number(info.year),
+                number(info.track_number),
+                number(info.disc_number),
+            );
+            return Ok(Some(template::render(
+                format,
+                &[
+                    ("artist", &info.artist),
+                    ("title", &info.title),
+                    ("album", &info.album),
+                    ("genre", &info.genre),
+                    ("year", &year),
+                    ("track", &track),
+                    ("disc", &disc),
+                    ("progress", &progress),
+                    ("player", &name),
+                ],
+            )));
+        }
+        let queue = match extras.show_queue_pos {
+            true => player.mc_queue_pos().await?,
+            false => None,
+        }
+        .map(|(n, total)| format!(" (track {n} of {total})"))
+        .unwrap_or_default();
+        let bpm = match info.bpm {
+            bpm if extras.show_bpm && bpm > 0 => format!(" ({bpm} BPM)"),
+            _ => String::new(),
+        };
+        let progress = match progress.is_empty() {
+            true => progress,
+            false => format!(" {progress}"),
+        };
+        Ok(Some(format!(
+            "{}: {}{}{}{}",
+            name,
+            info.render(order),
+            queue,
+            bpm,
+            progress
+        )))
+    }
+
+    /// Print a line of track output
+    fn print(&self, line: String) {
+        if self.ascii {
+            println!("{}", to_ascii(&line));
+        } else {
+            println!("{line}");
+        }
+    }
+
+    /// Player chosen by --index, --focused or else `first_active`
+    async fn select<'a>(
+        &'a self,
+        config: &Config,
+        conn: &Connection,
+        list: &'a [Box<dyn MusicCtl>],
+    ) -> Result<&'a Box<dyn MusicCtl>, Error> {
+        if let Some(n) = self.index {
+            list.get(n).ok_or(Error::NoIndex(n))
+        } else if self.focused {
+            let command = config.focus.command.as_ref().ok_or(Error::NoFocusCommand)?;
+            Ok(focused_player(conn, command, list).await?)
+        } else {
+            Ok(first_active(
+                &self.instance,
+                &config.priority,
+                config.require_artist,
+                list,
+            )
+            .await?)
+        }
+    }
+}
+
+/// Additions to the track line printed by info
+#[derive(Debug, Default, Clone, Args)]
+struct Extras {
+    /// Show position of current track in the player's queue
+    #[clap(long)]
+    show_queue_pos: bool,
+
+    /// Show beats per minute of current track, when known
+    #[clap(long)]
+    show_bpm: bool,
+
+    /// Show a progress bar like [###-------] 1:23/4:56
+    #[clap(long)]
+    progress: bool,
+
+    /// Number of characters in the progress bar
+    #[clap(long, value_name = "N", default_value_t = 10)]
+    progress_width: usize,
+}
+
+#[derive(Debug, Clone, Subcommand)]
 enum Command {
     List,
     Play,
+    Pause,
+    /// Toggle between play and pause
+    Toggle,
+    /// Print playing, paused or stopped
+    Status,
     Stop,
     Next,
     Prev,
-    #[default]
-    Info,
-    Vinfo,
+    /// Seek forward (or backward if negative) by OFFSET seconds
+    Seek {
+        #[clap(allow_negative_numbers = true)]
+        offset: f64,
+    },
+    Restart,
+    /// Show current track (default)
+    Info {
+        /// Keep running and re-print the track every INTERVAL seconds
+        #[clap(long, conflicts_with_all = ["json", "waybar"])]
+        watch: bool,
+        #[clap(long, default_value = "2", value_parser = parse_interval, requires = "watch")]
+        interval: Duration,
+        #[clap(flatten)]
+        extras: Extras,
+    },
+    /// Print every metadata entry of the current track
+    Metadata,
+    /// Print the current track again each time it or the playback status changes
+    Monitor,
+    Vinfo {
+        /// Add Previous/Play-Pause/Next buttons to the notification and wait
+        /// for a click
+        #[clap(long, visible_alias = "interactive")]
+        with_actions: bool,
+        /// Close the notification after MS milliseconds
+        #[clap(long, value_name = "MS", default_value_t = 5000)]
+        timeout: i32,
+        /// Application name for the notification
+        #[clap(long, default_value = env!("CARGO_PKG_NAME"))]
+        app_name: String,
+        /// Icon for the notification [default: track cover]
+        #[clap(long)]
+        app_icon: Option<String>,
+        /// Show the notification without any icon
+        #[clap(long, conflicts_with = "app_icon")]
+        no_art: bool,
+    },
+    /// Toggle mute
     Mute,
+    /// Check that a notification server is available for vinfo
+    CanNotify,
+    /// List RadioTrayNG stations by group
+    Stations,
+    /// Show volume, or set it to LEVEL percent (+N/-N to change by N)
+    Volume {
+        #[clap(allow_negative_numbers = true, value_parser = parse_level)]
+        level: Option<Level>,
+    },
+    /// Show loop mode, or set it to MODE
+    Loop {
+        #[clap(value_enum)]
+        mode: Option<LoopMode>,
+    },
+    /// Toggle shuffle, or turn it on or off, and print the new state
+    Shuffle {
+        #[clap(value_enum)]
+        state: Option<Switch>,
+    },
+    /// Bring the player's window to the front
+    Raise,
+    /// Close the player
+    Quit,
+    /// Show which operations each player allows
+    Capabilities,
+    /// Print a completion script for SHELL
+    Completions {
+        shell: clap_complete::Shell,
+    },
+    /// Call a Player method by name (Play, Pause, Stop, Next, Previous, PlayPause)
+    Raw {
+        method: String,
+    },
+}
+
+impl Command {
+    fn is_transport(&self) -> bool {
+        matches!(
+            self,
+            Command::Play
+                | Command::Pause
+                | Command::Toggle
+                | Command::Stop
+                | Command::Next
+                | Command::Prev
+                | Command::Restart
+        )
+    }
+
+    /// Send a transport command to `player`
+    async fn transport(&self, player: &dyn MusicCtl) -> Result<(), Error> {
+        match self {
+            Command::Play => player.mc_play().await?,
+            Command::Pause => player.mc_pause().await?,
+            Command::Toggle => player.mc_toggle().await?,
+            Command::Stop => player.mc_stop().await?,
+            Command::Next => player.mc_next().await?,
+            Command::Prev => player.mc_prev().await?,
+            Command::Restart => player.mc_restart().await?,
+            _ => return Err(Error::NotTransport("--all")),
+        }
+        Ok(())
+    }
+}
+
+/// Errors of the command line tool on top of those of the library
+#[derive(Debug, Error)]
+enum Error {
+    #[error(transparent)]
+    Mc(#[from] McError),
+    #[error("No player at index {0}")]
+    NoIndex(usize),
+    #[error("No focus command set in config")]
+    NoFocusCommand,
+    #[error("{0} only applies to transport commands")]
+    NotTransport(&'static str),
+    #[error("{0} player(s) failed")]
+    Failed(usize),
+}
+
+impl Error {
+    /// Process exit status reporting this error
+    fn exit_code(&self) -> ExitCode {
+        match self {
+            Error::Mc(e) => e.exit_code(),
+            _ => ExitCode::FAILURE,
+        }
+    }
+}
+
+impl From<zbus::Error> for Error {
+    fn from(e: zbus::Error) -> Self {
+        Error::Mc(e.into())
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Mc(e.into())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Mc(e.into())
+    }
+}
+
+/// Loop mode as given on the command line
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum LoopMode {
+    None,
+    Track,
+    Playlist,
+}
+
+impl From<LoopMode> for LoopStatus {
+    fn from(x: LoopMode) -> Self {
+        match x {
+            LoopMode::None => LoopStatus::None,
+            LoopMode::Track => LoopStatus::Track,
+            LoopMode::Playlist => LoopStatus::Playlist,
+        }
+    }
+}
+
+/// Display order as given on the command line
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Order {
+    /// Artist - Title
+    ArtistTitle,
+    /// Title by Artist
+    TitleArtist,
+    /// 'Album' Title by Artist
+    AlbumTitleArtist,
+}
+
+impl From<Order> for DisplayOrder {
+    fn from(x: Order) -> Self {
+        match x {
+            Order::ArtistTitle => DisplayOrder::ArtistTitle,
+            Order::TitleArtist => DisplayOrder::TitleArtist,
+            Order::AlbumTitleArtist => DisplayOrder::AlbumTitleArtist,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Switch {
+    On,
+    Off,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Level {
+    Set(u8),
+    Change(i16),
+}
+
+/// Volume percentage, out of range values are clamped to 0-100
+fn parse_level(s: &str) -> Result<Level, std::num::ParseIntError> {
+    if s.starts_with(['+', '-']) {
+        Ok(Level::Change(s.parse::<i64>()?.clamp(-100, 100) as i16))
+    } else {
+        Ok(Level::Set(s.parse::<u64>()?.min(100) as u8))
+    }
+}
+
+/// Seconds between `info --watch` updates, which must be positive
+fn parse_interval(s: &str) -> Result<Duration, String> {
+    let secs: f64 = s
+        .parse()
+        .map_err(|e: std::num::ParseFloatError| e.to_string())?;
+    match Duration::try_from_secs_f64(secs) {
+        Ok(x) if !x.is_zero() => Ok(x),
+        _ => Err("must be a positive number of seconds".to_string()),
+    }
 }
 
 #[proxy(assume_defaults = true)]
@@ -51,26 +490,205 @@ trait Notifications {
         hints: HashMap<&str, &Value<'_>>,
         expire_timeout: i32,
     ) -> zbus::Result<u32>;
+
+    /// Returns (name, vendor, version, spec_version) of the notification server
+    fn get_server_information(&self) -> zbus::Result<(String, String, String, String)>;
+
+    #[zbus(signal)]
+    fn action_invoked(&self, id: u32, action_key: &str) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn notification_closed(&self, id: u32, reason: u32) -> zbus::Result<()>;
+}
+
+/// Action key and label pairs for `vinfo --with-actions`
+const ACTIONS: [&str; 6] = [
+    "prev",
+    "Previous",
+    "play-pause",
+    "Play/Pause",
+    "next",
+    "Next",
+];
+
+/// How long `vinfo --with-actions` waits for a button press
+const ACTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// File holding the id of the last vinfo notification
+fn last_id_path() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .filter(|x| !x.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join(format!("{}.notification", env!("CARGO_PKG_NAME")))
+}
+
+/// Id of the last vinfo notification, 0 (a new notification) if unknown
+fn read_last_id() -> u32 {
+    std::fs::read_to_string(last_id_path())
+        .ok()
+        .and_then(|x| x.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// Wait for an action on notification `id`, None if it was closed
+async fn wait_for_action(
+    mut invoked: ActionInvokedStream<'_>,
+    mut closed: NotificationClosedStream<'_>,
+    id: u32,
+) -> Result<Option<String>, McError> {
+    loop {
+        tokio::select! {
+            Some(x) = invoked.next() => {
+                let args = x.args()?;
+                if args.id == id {
+                    return Ok(Some(args.action_key.to_string()));
+                }
+            }
+            Some(x) = closed.next() => {
+                if x.args()?.id == id {
+                    return Ok(None);
+                }
+            }
+            else => return Ok(None),
+        }
+    }
 }
 
-async fn first_active<'a>(
-    name: &'a Option<String>,
+/// Select player matching output of the focus hook, a PID or an id
+/// contained in the player's bus name
+async fn focused_player<'a>(
+    conn: &Connection,
+    command: &str,
     list: &'a [Box<dyn MusicCtl>],
 ) -> Result<&'a Box<dyn MusicCtl>, McError> {
+    let output = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .output()
+        .await?;
+    let focus = String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .to_lowercase();
+    if focus.is_empty() {
+        return Err(McError::NoActive);
+    }
+    let pid = focus.parse::<u32>().ok();
+
     for item in list {
-        if item.mc_canplay().await? {
-            if let Some(name) = name {
-                if item.mc_name().await?.contains(name) {
-                    return Ok(item);
-                }
-            } else {
-                return Ok(item);
-            }
+        let busname = item.mc_busname().await?;
+        let found = match pid {
+            Some(pid) => get_connection_pid(conn, &busname)
+                .await
+                .is_ok_and(|x| x == pid),
+            None => busname.to_lowercase().contains(&focus),
+        };
+        if found {
+            return Ok(item);
         }
     }
     Err(McError::NoActive)
 }
 
+/// JSON object of a player and its track, track fields are null when
+/// nothing is playing
+fn info_json(player: &str, info: Option<&MusicInfo>) -> Result<serde_json::Value, McError> {
+    let mut obj = match serde_json::to_value(info.cloned().unwrap_or_default())? {
+        serde_json::Value::Object(x) => x,
+        _ => unreachable!(),
+    };
+    if info.is_none() {
+        obj.values_mut().for_each(|x| *x = serde_json::Value::Null);
+    }
+    obj.insert("player".to_string(), player.into());
+    Ok(obj.into())
+}
+
+/// `[###-------] 1:23/4:56` bar `width` characters wide, just the elapsed
+/// time when `length` is unknown
+fn progress_bar(position: u64, length: u64, width: usize) -> String {
+    if length == 0 {
+        return format_duration(position);
+    }
+    let filled = (position.min(length) as f64 / length as f64 * width as f64).round() as usize;
+    format!(
+        "[{}{}] {}/{}",
+        "#".repeat(filled),
+        "-".repeat(width - filled),
+        format_duration(position),
+        format_duration(length)
+    )
+}
+
+/// Waybar custom module object for the current track
+fn waybar_json(
+    text: &str,
+    info: Option<&MusicInfo>,
+    status: PlaybackStatus,
+    position: Option<u64>,
+) -> serde_json::Value {
+    let mut obj = serde_json::Map::new();
+    obj.insert("text".to_string(), text.into());
+    let tooltip = info
+        .map(|x| {
+            [&x.album, &x.title, &x.artist]
+                .into_iter()
+                .filter(|x| !x.is_empty())
+                .cloned()
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .unwrap_or_default();
+    obj.insert("tooltip".to_string(), tooltip.into());
+    obj.insert("class".to_string(), status.to_string().into());
+    let length = info.map(|x| x.length).unwrap_or_default();
+    if let Some(position) = position.filter(|_| length > 0) {
+        let percentage = (position.min(length) * 100 / length) as u8;
+        obj.insert("percentage".to_string(), percentage.into());
+    }
+    obj.into()
+}
+
+/// Replace the command argument with its target from the alias map
+fn resolve_alias(mut args: Vec<OsString>, aliases: &HashMap<String, String>) -> Vec<OsString> {
+    let app = App::command();
+    let takes_value = |f: &dyn Fn(&clap::Arg) -> bool| {
+        app.get_arguments()
+            .any(|x| f(x) && x.get_action().takes_values())
+    };
+
+    let mut iter = args.iter_mut().skip(1);
+    while let Some(arg) = iter.next() {
+        let Some(s) = arg.to_str() else {
+            break;
+        };
+        if s == "--" {
+            continue;
+        }
+        if let Some(long) = s.strip_prefix("--") {
+            if !long.contains('=') && takes_value(&|x| x.get_long() == Some(long)) {
+                iter.next();
+            }
+            continue;
+        }
+        if let Some(short) = s.strip_prefix('-') {
+            if short
+                .chars()
+                .last()
+                .is_some_and(|c| takes_value(&|x| x.get_short() == Some(c)))
+            {
+                iter.next();
+            }
+            continue;
+        }
+        if let Some(target) = aliases.get(s) {
+            *arg = target.into();
+        }
+        break;
+    }
+    args
+}
+
 #[tokio::main]
 async fn main() -> ExitCode {
     match run().await {
@@ -78,62 +696,546 @@ async fn main() -> ExitCode {
         Err(e) => {
             eprintln!("Error: {e}");
 
-            ExitCode::FAILURE
+            e.exit_code()
         }
     }
 }
 
-async fn run() -> Result<(), McError> {
-    let cmd = App::parse();
-    let session = Connection::session().await?;
+async fn run() -> Result<(), Error> {
+    // A broken config drops the aliases with a warning, so --help still
+    // works; it is only fatal once a command needs the config
+    let config = Config::load();
+    let aliases = match &config {
+        Ok(config) => config.aliases.clone(),
+        Err(_) => {
+            eprintln!("Warning: cannot load config, ignoring aliases");
+            HashMap::new()
+        }
+    };
+    let cmd = App::parse_from(resolve_alias(std::env::args_os().collect(), &aliases));
+    if cmd.debug {
+        // Only our own events, zbus is very chatty at trace level
+        tracing_subscriber::registry()
+            .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+            .with(Targets::new().with_target(env!("CARGO_CRATE_NAME"), tracing::Level::TRACE))
+            .init();
+    }
+
+    let command = cmd.command.clone().unwrap_or(Command::Info {
+        watch: false,
+        interval: Duration::from_secs(2),
+        extras: Extras::default(),
+    });
+
+    if let Command::Completions { shell } = command {
+        let mut app = App::command();
+        clap_complete::generate(
+            shell,
+            &mut app,
+            env!("CARGO_PKG_NAME"),
+            &mut std::io::stdout(),
+        );
+        return Ok(());
+    }
+
+    let (conn, bus) = match cmd.system {
+        true => (Connection::system().await?, "system"),
+        false => (Connection::session().await?, "session"),
+    };
+
+    // Commands not needing a player or the config
+    if let Command::CanNotify = command {
+        let proxy = NotificationsProxy::new(&conn).await?;
+        let (name, vendor, version, _) = proxy.get_server_information().await?;
+        println!("{name} {version} ({vendor})");
+        return Ok(());
+    }
 
-    let list = get_all(&session).await?;
+    let config = config?;
 
-    let active = first_active(&cmd.instance, &list).await?;
+    let ignore: Vec<String> = config.ignore.iter().chain(&cmd.ignore).cloned().collect();
+    let services = config.service_map();
+    let mpd_host = std::env::var("MUSICCTL_MPD_HOST")
+        .ok()
+        .or(config.mpd_host.clone());
+    let discover = || async {
+        let list = get_all(
+            &conn,
+            &services,
+            &ignore,
+            mpd_host.as_deref(),
+            Duration::from_millis(cmd.timeout_ms),
+        )
+        .await?;
+        Ok::<_, McError>(match cmd.prune {
+            true => prune(list).await,
+            false => list,
+        })
+    };
 
-    match cmd.command {
-        Command::List => {
-            join_all(list.iter().map(|x| async move {
+    let order = cmd
+        .display_order
+        .map(DisplayOrder::from)
+        .or(config.display_order)
+        .unwrap_or_default();
+
+    if cmd.all && !command.is_transport() {
+        return Err(Error::NotTransport("--all"));
+    }
+
+    if let Command::Info {
+        watch: true,
+        interval,
+        ref extras,
+    } = command
+    {
+        let mut ticks = tokio::time::interval(interval);
+        // Registered up front so a Ctrl-C during a slow tick isn't lost
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        loop {
+            tokio::select! {
+                _ = ticks.tick() => {}
+                _ = interrupt.recv() => break,
+            }
+            let line = async {
+                let list = discover().await?;
+                let active = cmd.select(&config, &conn, &list).await?;
+                Ok::<_, Error>(
+                    cmd.track_line(active.as_ref(), order, extras)
+                        .await?
+                        .unwrap_or_default(),
+                )
+            }
+            .await;
+            // Players come and go between ticks, show nothing until one is back
+            let line = match line {
+                Ok(x) => x,
+                Err(e @ Error::Mc(McError::NoActive | McError::Timeout | McError::Zbus(_))) => {
+                    debug!("No track: {e}");
+                    String::new()
+                }
+                Err(e) => return Err(e),
+            };
+            let line = if cmd.ascii { to_ascii(&line) } else { line };
+            // Overwrite the previous line
+            print!("\r\x1b[2K{line}");
+            std::io::stdout().flush()?;
+        }
+        println!();
+        return Ok(());
+    }
+
+    let list = discover().await?;
+    if list.is_empty() {
+        return Err(McError::NoPlayers(bus).into());
+    }
+
+    if let Command::Stations = command {
+        for item in &list {
+            let groups = match item.mc_stations().await {
+                Err(McError::Unsupported(_)) => continue,
+                x => x?,
+            };
+            for (group, stations) in groups {
+                println!("{group}:");
+                for station in stations {
+                    println!("  {station}");
+                }
+            }
+            return Ok(());
+        }
+        return Err(McError::Unsupported("stations").into());
+    }
+
+    if let Command::Capabilities = command {
+        let mut rows = Vec::new();
+        for item in &list {
+            // One player failing only costs its own row
+            let caps = item.mc_capabilities().await;
+            rows.push((cmd.player_name(item.as_ref()).await?, caps));
+        }
+        let width = rows
+            .iter()
+            .map(|(name, _)| name.len())
+            .fold("PLAYER".len(), usize::max);
+        let yes = |x: bool| if x { "yes" } else { "no" };
+        println!(
+            "{:width$}  PLAY  PAUSE  NEXT  PREV  SEEK  CONTROL",
+            "PLAYER"
+        );
+        for (name, x) in rows {
+            let x = match x {
+                Ok(x) => x,
+                Err(e) => {
+                    println!("{name:width$}  error: {e}");
+                    continue;
+                }
+            };
+            println!(
+                "{name:width$}  {:4}  {:5}  {:4}  {:4}  {:4}  {}",
+                yes(x.play),
+                yes(x.pause),
+                yes(x.next),
+                yes(x.prev),
+                yes(x.seek),
+                yes(x.control),
+            );
+        }
+        return Ok(());
+    }
+
+    // Listing must work while no player can play
+    if let Command::List = command {
+        let cmd = &cmd;
+        if cmd.json {
+            let xs = join_all(list.iter().map(|x| async move {
+                info_json(
+                    &cmd.player_name(x.as_ref()).await.unwrap_or_default(),
+                    x.mc_info().await.unwrap_or_default().as_ref(),
+                )
+            }))
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+            println!("{}", serde_json::Value::Array(xs));
+        } else {
+            join_all(list.iter().enumerate().map(|(i, x)| async move {
                 if cmd.debug {
                     println!(
-                        "{}: {:?}",
-                        x.mc_name().await.unwrap_or_default(),
+                        "[{i}] {}: {:?}",
+                        cmd.player_name(x.as_ref()).await.unwrap_or_default(),
                         x.mc_info().await
                     );
                 } else if let Some(info) = x.mc_info().await.unwrap_or_default() {
-                    println!("{}: {}", x.mc_name().await.unwrap_or_default(), info);
+                    cmd.print(format!(
+                        "[{i}] {}{}{}",
+                        cmd.player_name(x.as_ref()).await.unwrap_or_default(),
+                        cmd.separator,
+                        info.render(order)
+                    ));
                 }
             }))
             .await;
         }
-        Command::Info => {
-            if let Some(info) = active.mc_info().await? {
-                println!("{}: {}", active.mc_name().await?, info);
+        return Ok(());
+    }
+
+    if cmd.all {
+        let results = join_all(list.iter().map(|x| async {
+            match x.mc_canplay().await {
+                Ok(true) => Some(command.transport(x.as_ref()).await),
+                Ok(false) => None,
+                Err(e) => Some(Err(e.into())),
             }
+        }))
+        .await;
+        if results.iter().all(Option::is_none) {
+            return Err(McError::NoActive.into());
+        }
+        let mut failed = 0;
+        for (x, result) in list.iter().zip(results) {
+            if let Some(Err(e)) = result {
+                eprintln!("Error: {}: {e}", x.mc_name().await.unwrap_or_default());
+                failed += 1;
+            }
+        }
+        return match failed {
+            0 => Ok(()),
+            n => Err(Error::Failed(n)),
+        };
+    }
+
+    let active = cmd.select(&config, &conn, &list).await?;
+
+    match command {
+        Command::Info { .. } if cmd.json => {
+            let info = active.mc_info().await?;
+            println!(
+                "{}",
+                info_json(&cmd.player_name(active.as_ref()).await?, info.as_ref())?
+            );
+        }
+        Command::Info { ref extras, .. } if cmd.waybar => {
+            let info = active.mc_info().await?;
+            let text = cmd
+                .track_line(active.as_ref(), order, extras)
+                .await?
+                .unwrap_or_default();
+            let text = if cmd.ascii { to_ascii(&text) } else { text };
+            let position = match active.mc_position().await {
+                Err(McError::Unsupported(_)) => None,
+                x => Some(x?),
+            };
+            let status = active.mc_status().await?;
+            println!("{}", waybar_json(&text, info.as_ref(), status, position));
         }
-        Command::Play => active.mc_play().await?,
-        Command::Stop => active.mc_stop().await?,
-        Command::Next => active.mc_next().await?,
-        Command::Prev => active.mc_prev().await?,
-        Command::Vinfo => {
+        Command::Info { ref extras, .. } => {
+            if cmd.debug && active.mc_kind() != PluginKind::Mpd {
+                let busname = active.mc_busname().await?;
+                let owner = get_name_owner(&conn, &busname).await?;
+                debug!("Using {busname}: {owner}");
+            }
+            if let Some(line) = cmd.track_line(active.as_ref(), order, extras).await? {
+                cmd.print(line);
+            }
+        }
+        Command::Monitor => {
+            let busname = active.mc_busname().await?;
+            let mut changes = active.mc_changes().await?;
+            let dbus = fdo::DBusProxy::new(&conn).await?;
+            let mut owner = dbus
+                .receive_name_owner_changed_with_args(&[(0, busname.as_str())])
+                .await?;
+            let mut last = None;
+            loop {
+                let current = (
+                    active.mc_status().await?,
+                    cmd.track_line(active.as_ref(), order, &Extras::default())
+                        .await?,
+                );
+                if last.as_ref() != Some(&current) {
+                    if let Some(line) = &current.1 {
+                        cmd.print(line.clone());
+                    }
+                    last = Some(current);
+                }
+                tokio::select! {
+                    x = changes.next() => if x.is_none() {
+                        break;
+                    },
+                    x = owner.next() => match x.map(|x| x.args().map(|x| x.new_owner.is_none())) {
+                        Some(Ok(false)) => {}
+                        _ => break,
+                    },
+                }
+            }
+        }
+        Command::Play
+        | Command::Pause
+        | Command::Toggle
+        | Command::Stop
+        | Command::Next
+        | Command::Prev
+        | Command::Restart => command.transport(active.as_ref()).await?,
+        Command::Status => println!("{}", active.mc_status().await?),
+        Command::Seek { offset } => active.mc_seek((offset * 1_000_000.0) as i64).await?,
+        Command::Raw { method } => active.mc_raw(&method).await?,
+        Command::Metadata => {
+            let xs: BTreeMap<_, _> = active.mc_raw_metadata().await?.into_iter().collect();
+            for (k, v) in xs {
+                cmd.print(format!("{k}: {v}"));
+            }
+        }
+        Command::Raise => active.mc_raise().await?,
+        Command::Quit => active.mc_quit().await?,
+        Command::Mute => active.mc_mute().await?,
+        Command::Loop { mode: None } => println!("{}", active.mc_get_loop().await?),
+        Command::Loop { mode: Some(mode) } => active.mc_set_loop(mode.into()).await?,
+        Command::Shuffle { state } => {
+            let on = match state {
+                Some(Switch::On) => true,
+                Some(Switch::Off) => false,
+                None => !active.mc_get_shuffle().await?,
+            };
+            active.mc_set_shuffle(on).await?;
+            println!("{}", if on { "on" } else { "off" });
+        }
+        Command::Volume { level: None } => {
+            println!("{:.0}", active.mc_get_volume().await? * 100.0);
+        }
+        Command::Volume {
+            level: Some(Level::Set(level)),
+        } => {
+            active.mc_set_volume(f64::from(level) / 100.0).await?;
+        }
+        Command::Volume {
+            level: Some(Level::Change(delta)),
+        } => {
+            let current = (active.mc_get_volume().await? * 100.0).round();
+            let level = (current + f64::from(delta)).clamp(0.0, 100.0);
+            if level != current {
+                active.mc_set_volume(level / 100.0).await?;
+            }
+            println!("{level:.0}");
+        }
+        Command::Vinfo {
+            with_actions,
+            timeout,
+            app_name,
+            app_icon,
+            no_art,
+        } => {
             if let Some(info) = active.mc_info().await.unwrap_or_default() {
-                let proxy = NotificationsProxy::new(&session).await?;
+                let icon = match (no_art, app_icon) {
+                    (true, _) => String::new(),
+                    (false, Some(icon)) => icon,
+                    (false, None) => cover::local_cover(&info.cover).await,
+                };
+                let proxy = NotificationsProxy::new(&conn).await?;
+                // Subscribe before sending so no click is missed
+                let signals = match with_actions {
+                    true => Some((
+                        proxy.receive_action_invoked().await?,
+                        proxy.receive_notification_closed().await?,
+                    )),
+                    false => None,
+                };
                 let id = proxy
                     .notify(
-                        env!("CARGO_PKG_NAME"),
-                        0,
-                        &info.cover,
-                        &info.to_string(),
-                        &active.mc_name().await?,
-                        &[],
+                        &app_name,
+                        read_last_id(),
+                        &icon,
+                        &info.render(order),
+                        &cmd.player_name(active.as_ref()).await?,
+                        if with_actions { &ACTIONS } else { &[] },
                         HashMap::new(),
-                        0,
+                        timeout,
                     )
                     .await?;
+                // Losing the id only means the next popup won't replace this one
+                let _ = std::fs::write(last_id_path(), id.to_string());
                 println!("Created Notification: {id}");
+
+                if let Some((invoked, closed)) = signals {
+                    let action =
+                        tokio::time::timeout(ACTION_TIMEOUT, wait_for_action(invoked, closed, id))
+                            .await;
+                    match action {
+                        Ok(Ok(Some(x))) if x == "next" => active.mc_next().await?,
+                        Ok(Ok(Some(x))) if x == "prev" => active.mc_prev().await?,
+                        Ok(Ok(Some(x))) if x == "play-pause" => active.mc_toggle().await?,
+                        Ok(Err(e)) => return Err(e.into()),
+                        _ => {}
+                    }
+                }
             }
         }
-        Command::Mute => todo!(),
+        Command::CanNotify
+        | Command::List
+        | Command::Stations
+        | Command::Capabilities
+        | Command::Completions { .. } => unreachable!(),
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(args: &[&str]) -> Vec<String> {
+        let aliases = HashMap::from([("pp".to_string(), "toggle".to_string())]);
+        let args = args.iter().map(OsString::from).collect();
+        resolve_alias(args, &aliases)
+            .into_iter()
+            .map(|x| x.into_string().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn resolve_alias_command() {
+        assert_eq!(resolve(&["musicctl", "pp"]), ["musicctl", "toggle"]);
+    }
+
+    #[test]
+    fn resolve_alias_unknown_command() {
+        assert_eq!(resolve(&["musicctl", "next"]), ["musicctl", "next"]);
+    }
+
+    #[test]
+    fn resolve_alias_skips_option_value() {
+        assert_eq!(
+            resolve(&["musicctl", "--timeout-ms", "10", "pp"]),
+            ["musicctl", "--timeout-ms", "10", "toggle"]
+        );
+    }
+
+    #[test]
+    fn resolve_alias_inline_option_value() {
+        assert_eq!(
+            resolve(&["musicctl", "--instance=vlc", "pp"]),
+            ["musicctl", "--instance=vlc", "toggle"]
+        );
+    }
+
+    #[test]
+    fn resolve_alias_clustered_short_flags() {
+        assert_eq!(
+            resolve(&["musicctl", "-di", "vlc", "pp"]),
+            ["musicctl", "-di", "vlc", "toggle"]
+        );
+    }
+
+    #[test]
+    fn resolve_alias_after_double_dash() {
+        assert_eq!(
+            resolve(&["musicctl", "--", "pp"]),
+            ["musicctl", "--", "toggle"]
+        );
+    }
+
+    #[test]
+    fn resolve_alias_as_option_value() {
+        assert_eq!(
+            resolve(&["musicctl", "--instance", "pp", "info"]),
+            ["musicctl", "--instance", "pp", "info"]
+        );
+    }
+
+    #[test]
+    fn resolve_alias_only_first_command() {
+        assert_eq!(
+            resolve(&["musicctl", "raw", "pp"]),
+            ["musicctl", "raw", "pp"]
+        );
+    }
+
+    #[test]
+    fn progress_bar_partial() {
+        assert_eq!(
+            progress_bar(60_000_000, 240_000_000, 8),
+            "[##------] 1:00/4:00"
+        );
+    }
+
+    #[test]
+    fn progress_bar_zero_length() {
+        assert_eq!(progress_bar(83_000_000, 0, 8), "1:23");
+    }
+
+    #[test]
+    fn progress_bar_past_length() {
+        assert_eq!(
+            progress_bar(300_000_000, 240_000_000, 4),
+            "[####] 5:00/4:00"
+        );
+    }
+
+    #[test]
+    fn parse_level_set() {
+        assert_eq!(parse_level("40"), Ok(Level::Set(40)));
+    }
+
+    #[test]
+    fn parse_level_set_clamped() {
+        assert_eq!(parse_level("250"), Ok(Level::Set(100)));
+    }
+
+    #[test]
+    fn parse_level_change_zero() {
+        assert_eq!(parse_level("+0"), Ok(Level::Change(0)));
+        assert_eq!(parse_level("-0"), Ok(Level::Change(0)));
+    }
+
+    #[test]
+    fn parse_level_change_clamped() {
+        assert_eq!(parse_level("+150"), Ok(Level::Change(100)));
+        assert_eq!(parse_level("-150"), Ok(Level::Change(-100)));
+    }
+
+    #[test]
+    fn parse_level_invalid() {
+        assert!(parse_level("loud").is_err());
+        assert!(parse_level("+").is_err());
+    }
+}
@@ -1,55 +1,277 @@
 // Copyright 2023 (c) Nathaniel Clark
 
-mod mpris;
-mod radiotray;
-mod shairportsync;
+pub(crate) mod mpd;
+pub(crate) mod mpris;
+pub(crate) mod radiotray;
+pub(crate) mod shairportsync;
+mod timed;
 
 use async_trait::async_trait;
-use futures::future::try_join_all;
-use std::collections::HashMap;
+use futures::{future::join_all, stream::BoxStream};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, process::ExitCode, time::Duration};
 use thiserror::Error;
-use zbus::{proxy, Connection};
-use zvariant::Value;
+use tracing::{debug, instrument, trace};
+use zbus::{fdo, proxy, Connection};
+use zvariant::{OwnedValue, Value};
 
 #[derive(Debug, Error)]
-pub(crate) enum McError {
+pub enum McError {
     #[error(transparent)]
     Zbus(#[from] zbus::Error),
     #[error(transparent)]
     Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Toml(#[from] toml::de::Error),
     #[error("No active players avaiable")]
     NoActive,
+    #[error("No players found on the {0} bus")]
+    NoPlayers(&'static str),
+    #[error("Player did not answer in time")]
+    Timeout,
+    #[error("MPD: {0}")]
+    Mpd(String),
+    #[error("{0} is not supported by this player")]
+    Unsupported(&'static str),
+    #[error("{0} is not an allowed method")]
+    UnknownMethod(String),
+}
+
+impl McError {
+    /// Process exit status reporting this error
+    pub fn exit_code(&self) -> ExitCode {
+        match self {
+            McError::NoActive | McError::NoPlayers(_) => ExitCode::from(3),
+            McError::Unsupported(_) => ExitCode::from(4),
+            McError::Timeout => ExitCode::from(5),
+            _ => ExitCode::FAILURE,
+        }
+    }
 }
 
+/// Operations on one player; those a player may lack return
+/// `McError::Unsupported` unless implemented
 #[async_trait]
-pub(crate) trait MusicCtl {
-    // play/pause
+pub trait MusicCtl: Send + Sync {
     async fn mc_play(&self) -> Result<(), McError>;
+    async fn mc_pause(&self) -> Result<(), McError>;
+    // play/pause
+    async fn mc_toggle(&self) -> Result<(), McError>;
     async fn mc_stop(&self) -> Result<(), McError>;
     async fn mc_name(&self) -> Result<String, McError>;
+    fn mc_kind(&self) -> PluginKind;
+    async fn mc_busname(&self) -> Result<String, McError>;
     async fn mc_info(&self) -> Result<Option<MusicInfo>, McError>;
+    // every metadata entry, stringified
+    async fn mc_raw_metadata(&self) -> Result<HashMap<String, String>, McError>;
     async fn mc_next(&self) -> Result<(), McError>;
     async fn mc_prev(&self) -> Result<(), McError>;
+    // relative seek
+    async fn mc_seek(&self, _offset_micros: i64) -> Result<(), McError> {
+        Err(McError::Unsupported("seek"))
+    }
+    // seek to start of current track
+    async fn mc_restart(&self) -> Result<(), McError> {
+        Err(McError::Unsupported("restart"))
+    }
+    // call a whitelisted transport method by its D-Bus name
+    async fn mc_raw(&self, _method: &str) -> Result<(), McError> {
+        Err(McError::Unsupported("raw"))
+    }
+    // (group, station names) of bookmarked stations
+    async fn mc_stations(&self) -> Result<Vec<(String, Vec<String>)>, McError> {
+        Err(McError::Unsupported("stations"))
+    }
+    // volume from 0.0 to 1.0
+    async fn mc_get_volume(&self) -> Result<f64, McError> {
+        Err(McError::Unsupported("volume"))
+    }
+    async fn mc_set_volume(&self, _v: f64) -> Result<(), McError> {
+        Err(McError::Unsupported("volume"))
+    }
     async fn mc_canplay(&self) -> Result<bool, McError>;
+    async fn mc_status(&self) -> Result<PlaybackStatus, McError>;
+    async fn mc_get_loop(&self) -> Result<LoopStatus, McError> {
+        Err(McError::Unsupported("loop"))
+    }
+    async fn mc_set_loop(&self, _status: LoopStatus) -> Result<(), McError> {
+        Err(McError::Unsupported("loop"))
+    }
+    async fn mc_get_shuffle(&self) -> Result<bool, McError> {
+        Err(McError::Unsupported("shuffle"))
+    }
+    async fn mc_set_shuffle(&self, _on: bool) -> Result<(), McError> {
+        Err(McError::Unsupported("shuffle"))
+    }
+    // position in current track in microseconds
+    async fn mc_position(&self) -> Result<u64, McError> {
+        Err(McError::Unsupported("position"))
+    }
+    // (index, total) of current track in the player's queue
+    async fn mc_queue_pos(&self) -> Result<Option<(usize, usize)>, McError> {
+        Ok(None)
+    }
+    // toggle mute
+    async fn mc_mute(&self) -> Result<(), McError> {
+        Err(McError::Unsupported("mute"))
+    }
+    // bring the player's window to the front
+    async fn mc_raise(&self) -> Result<(), McError> {
+        Err(McError::Unsupported("raise"))
+    }
+    // close the player
+    async fn mc_quit(&self) -> Result<(), McError> {
+        Err(McError::Unsupported("quit"))
+    }
+    // operations the player currently allows
+    async fn mc_capabilities(&self) -> Result<Capabilities, McError>;
+    // yields whenever the track or playback status changes
+    async fn mc_changes(&self) -> Result<BoxStream<'static, ()>, McError> {
+        Err(McError::Unsupported("monitor"))
+    }
+}
+
+/// Operations a player allows
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    pub play: bool,
+    pub pause: bool,
+    pub next: bool,
+    pub prev: bool,
+    pub seek: bool,
+    pub control: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackStatus {
+    Playing,
+    Paused,
+    Stopped,
 }
 
-#[derive(Debug, Clone)]
-pub(crate) struct MusicInfo {
+impl std::fmt::Display for PlaybackStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlaybackStatus::Playing => write!(f, "playing"),
+            PlaybackStatus::Paused => write!(f, "paused"),
+            PlaybackStatus::Stopped => write!(f, "stopped"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopStatus {
+    None,
+    Track,
+    Playlist,
+}
+
+impl LoopStatus {
+    /// Value of the MPRIS LoopStatus property
+    pub fn as_mpris(&self) -> &'static str {
+        match self {
+            LoopStatus::None => "None",
+            LoopStatus::Track => "Track",
+            LoopStatus::Playlist => "Playlist",
+        }
+    }
+
+    pub fn from_mpris(s: &str) -> Option<Self> {
+        match s {
+            "None" => Some(LoopStatus::None),
+            "Track" => Some(LoopStatus::Track),
+            "Playlist" => Some(LoopStatus::Playlist),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for LoopStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_mpris().to_lowercase())
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct MusicInfo {
     pub artist: String,
     pub title: String,
     pub album: String,
     pub cover: String,
+    pub genre: String,
+    pub year: Option<u32>,
+    pub track_number: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub bpm: u32,
+    // microseconds, 0 when unknown
+    pub length: u64,
+    // microseconds, only set when requested
+    pub position: Option<u64>,
 }
 
-impl std::fmt::Display for MusicInfo {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if !self.album.is_empty() {
-            write!(f, "'{}' ", self.album)?;
+/// Format microseconds as m:ss (or h:mm:ss)
+pub fn format_duration(micros: u64) -> String {
+    let secs = micros / 1_000_000;
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if h > 0 {
+        format!("{h}:{m:02}:{s:02}")
+    } else {
+        format!("{m}:{s:02}")
+    }
+}
+
+/// Order of fields in human readable track output
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DisplayOrder {
+    /// Artist - Title
+    ArtistTitle,
+    /// Title by Artist
+    TitleArtist,
+    /// 'Album' Title by Artist
+    #[default]
+    AlbumTitleArtist,
+}
+
+impl MusicInfo {
+    pub fn render(&self, order: DisplayOrder) -> String {
+        let mut out = String::new();
+        match order {
+            DisplayOrder::ArtistTitle => {
+                out.push_str(&self.artist);
+                if !self.title.is_empty() {
+                    if !out.is_empty() {
+                        out.push_str(" - ");
+                    }
+                    out.push_str(&self.title);
+                }
+            }
+            DisplayOrder::TitleArtist | DisplayOrder::AlbumTitleArtist => {
+                if order == DisplayOrder::AlbumTitleArtist && !self.album.is_empty() {
+                    out.push_str(&format!("'{}' ", self.album));
+                }
+                if !self.title.is_empty() {
+                    out.push_str(&format!("{} by ", self.title));
+                }
+                out.push_str(&self.artist);
+            }
         }
-        if !self.title.is_empty() {
-            write!(f, "{} by ", self.title)?;
+        if let Some(position) = self.position.filter(|_| self.length > 0) {
+            out.push_str(&format!(
+                " ({}/{})",
+                format_duration(position),
+                format_duration(self.length)
+            ));
         }
-        write!(f, "{}", self.artist)
+        out
+    }
+}
+
+impl std::fmt::Display for MusicInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.render(DisplayOrder::default()))
     }
 }
 
@@ -60,7 +282,7 @@ impl TryFrom<HashMap<String, Value<'_>>> for MusicInfo {
         Ok(MusicInfo {
             artist: xs
                 .get("xesam:artist")
-                .map(variant_val_to_string)
+                .map(variant_list_to_string)
                 .unwrap_or_default(),
             title: xs
                 .get("xesam:title")
@@ -74,13 +296,58 @@ impl TryFrom<HashMap<String, Value<'_>>> for MusicInfo {
                 .get("mpris:artUrl")
                 .map(variant_val_to_string)
                 .unwrap_or_default(),
+            genre: xs
+                .get("xesam:genre")
+                .map(variant_list_to_string)
+                .unwrap_or_default(),
+            // ISO 8601 date, e.g. 2007-04-29T13:56+01:00
+            year: xs
+                .get("xesam:contentCreated")
+                .map(variant_val_to_string)
+                .and_then(|x| x.get(..4)?.parse().ok()),
+            track_number: xs
+                .get("xesam:trackNumber")
+                .and_then(variant_val_to_int)
+                .and_then(|x| u32::try_from(x).ok()),
+            disc_number: xs
+                .get("xesam:discNumber")
+                .and_then(variant_val_to_int)
+                .and_then(|x| u32::try_from(x).ok()),
+            bpm: xs
+                .get("xesam:audioBPM")
+                .and_then(variant_val_to_int)
+                .and_then(|x| u32::try_from(x).ok())
+                .unwrap_or_default(),
+            length: xs
+                .get("mpris:length")
+                .and_then(variant_val_to_int)
+                .and_then(|x| u64::try_from(x).ok())
+                .unwrap_or_default(),
+            position: None,
         })
     }
 }
 
 #[proxy(assume_defaults = true)]
-trait DBus {
+pub trait DBus {
     fn list_names(&self) -> zbus::Result<Vec<String>>;
+    fn get_name_owner(&self, name: &str) -> zbus::Result<String>;
+    #[zbus(name = "GetConnectionUnixProcessID")]
+    fn get_connection_unix_process_id(&self, name: &str) -> zbus::Result<u32>;
+}
+
+/// Unique connection name (`:1.x`) currently owning well-known `name`
+pub async fn get_name_owner(conn: &Connection, name: &str) -> Result<String, McError> {
+    let proxy = DBusProxy::new(conn).await?;
+
+    Ok(proxy.get_name_owner(name).await?)
+}
+
+/// Process ID of the connection owning `name`
+pub async fn get_connection_pid(conn: &Connection, name: &str) -> Result<u32, McError> {
+    let proxy = DBusProxy::new(conn).await?;
+
+    Ok(proxy.get_connection_unix_process_id(name).await?)
 }
 
 pub(crate) fn get_json_string(xs: &serde_json::Value, key: &str) -> String {
@@ -93,45 +360,377 @@ pub(crate) fn get_json_string(xs: &serde_json::Value, key: &str) -> String {
 pub(crate) fn variant_val_to_string(x: &zbus::zvariant::Value) -> String {
     match x {
         zbus::zvariant::Value::Str(s) => s.to_string(),
-        zbus::zvariant::Value::Array(a) => variant_val_to_string(&a[0]),
-        _ => todo!(),
+        zbus::zvariant::Value::ObjectPath(s) => s.to_string(),
+        zbus::zvariant::Value::Bool(b) => b.to_string(),
+        zbus::zvariant::Value::F64(f) => f.to_string(),
+        zbus::zvariant::Value::U64(i) => i.to_string(),
+        zbus::zvariant::Value::Value(x) => variant_val_to_string(x),
+        zbus::zvariant::Value::Array(a) => a.first().map(variant_val_to_string).unwrap_or_default(),
+        zbus::zvariant::Value::Dict(d) => d
+            .iter()
+            .map(|(k, v)| format!("{}: {}", variant_val_to_string(k), variant_val_to_string(v)))
+            .collect::<Vec<_>>()
+            .join(", "),
+        x => variant_val_to_int(x)
+            .map(|i| i.to_string())
+            .unwrap_or_else(|| format!("{x:?}")),
+    }
+}
+
+/// All elements of an array joined with `, `, other values as by
+/// `variant_val_to_string`
+pub(crate) fn variant_list_to_string(x: &zbus::zvariant::Value) -> String {
+    match x {
+        zbus::zvariant::Value::Value(x) => variant_list_to_string(x),
+        zbus::zvariant::Value::Array(a) => a
+            .iter()
+            .map(variant_val_to_string)
+            .collect::<Vec<_>>()
+            .join(", "),
+        x => variant_val_to_string(x),
+    }
+}
+
+/// Unwrap a metadata dict some players send wrapped in an extra variant
+pub(crate) fn unwrap_metadata(x: Value<'_>) -> Result<HashMap<String, Value<'_>>, McError> {
+    match x {
+        Value::Value(x) => unwrap_metadata(*x),
+        Value::Dict(x) => Ok(x.try_into().map_err(zbus::Error::from)?),
+        _ => Err(zbus::Error::from(zvariant::Error::IncorrectType).into()),
+    }
+}
+
+/// Metadata property of `proxy`, also for players wrapping the dict in a
+/// variant; repeated reads are served from the proxy's property cache
+pub(crate) async fn get_metadata(
+    proxy: &zbus::Proxy<'_>,
+) -> Result<HashMap<String, Value<'static>>, McError> {
+    unwrap_metadata(proxy.get_property::<OwnedValue>("Metadata").await?.into())
+}
+
+pub(crate) fn variant_val_to_int(x: &zbus::zvariant::Value) -> Option<i64> {
+    match x {
+        zbus::zvariant::Value::U8(i) => Some((*i).into()),
+        zbus::zvariant::Value::I16(i) => Some((*i).into()),
+        zbus::zvariant::Value::U16(i) => Some((*i).into()),
+        zbus::zvariant::Value::I32(i) => Some((*i).into()),
+        zbus::zvariant::Value::U32(i) => Some((*i).into()),
+        zbus::zvariant::Value::I64(i) => Some(*i),
+        zbus::zvariant::Value::U64(i) => i64::try_from(*i).ok(),
+        _ => None,
+    }
+}
+
+/// Plugin used to control a player's bus name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum PluginKind {
+    #[serde(rename = "mpris")]
+    Mpris,
+    #[serde(rename = "shairport")]
+    ShairportSync,
+    #[serde(rename = "radiotray")]
+    RadioTrayNG,
+    // reached over TCP, not a bus name
+    #[serde(skip)]
+    Mpd,
+}
+
+impl PluginKind {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            shairportsync::SERVICE_NAME => Some(PluginKind::ShairportSync),
+            radiotray::RADIOTRAY_NG => Some(PluginKind::RadioTrayNG),
+            x if x.starts_with(mpris::MPRIS_PREFIX) => Some(PluginKind::Mpris),
+            _ => None,
+        }
     }
 }
 
-pub(crate) async fn get_all(conn: &Connection) -> Result<Vec<Box<dyn MusicCtl>>, McError> {
+/// Find all supported players on the bus, `services` maps additional
+/// (or overrides) bus names to the plugin handling them, bus names
+/// containing an `ignore` entry (ignoring case) are left out, and the MPD
+/// server at `mpd_host` is added when it answers. Players which
+/// fail to connect within `timeout` are skipped (and logged),
+/// the others fail their calls with `McError::Timeout` after `timeout`.
+#[instrument(skip_all)]
+pub async fn get_all(
+    conn: &Connection,
+    services: &HashMap<String, PluginKind>,
+    ignore: &[String],
+    mpd_host: Option<&str>,
+    timeout: Duration,
+) -> Result<Vec<Box<dyn MusicCtl>>, McError> {
     let proxy = DBusProxy::new(conn).await?;
 
     let xs = proxy.list_names().await?;
+    trace!("bus names: {xs:?}");
+    let ignore: Vec<String> = ignore.iter().map(|x| x.to_lowercase()).collect();
 
-    let mut list: Vec<Box<dyn MusicCtl>> = try_join_all(
-        xs.iter()
-            .filter(|x| x.starts_with(mpris::MPRIS_PREFIX))
-            .map(|x| {
-                let conn = conn.clone();
-                async move {
-                    match x.as_str() {
-                        shairportsync::SERVICE_NAME => {
-                            shairportsync::ShairportSyncProxy::builder(&conn)
-                                .destination(x.to_string())?
-                                .build()
-                                .await
-                                .map(|x| Box::new(x) as Box<dyn MusicCtl>)
-                        }
-                        _ => mpris::Mpris2Proxy::builder(&conn)
-                            .destination(x.to_string())?
-                            .build()
-                            .await
-                            .map(|x| Box::new(x) as Box<dyn MusicCtl>),
-                    }
+    let mut found: Vec<(&String, PluginKind)> = xs
+        .iter()
+        .filter(|x| {
+            let name = x.to_lowercase();
+            let ignored = ignore.iter().any(|i| name.contains(i));
+            if ignored {
+                debug!("Ignoring {x}");
+            }
+            !ignored
+        })
+        .filter_map(|x| {
+            services
+                .get(x)
+                .copied()
+                .or_else(|| PluginKind::from_name(x))
+                .map(|kind| (x, kind))
+        })
+        .collect();
+    // RadioTrayNG is least preferred
+    found.sort_by_key(|(_, kind)| *kind == PluginKind::RadioTrayNG);
+    debug!("Found {found:?}");
+
+    let mut list: Vec<Box<dyn MusicCtl>> = join_all(found.into_iter().map(|(x, kind)| {
+        let conn = conn.clone();
+        async move {
+            trace!("Building {kind:?} proxy for {x}");
+            let proxy = tokio::time::timeout(timeout, async {
+                match kind {
+                    PluginKind::ShairportSync => shairportsync::ShairportSyncProxy::builder(&conn)
+                        .destination(x.to_string())?
+                        .build()
+                        .await
+                        .map(|x| Box::new(x) as Box<dyn MusicCtl>),
+                    PluginKind::RadioTrayNG => radiotray::RadioTrayNGProxy::builder(&conn)
+                        .destination(x.to_string())?
+                        .build()
+                        .await
+                        .map(|x| Box::new(x) as Box<dyn MusicCtl>),
+                    PluginKind::Mpris => mpris::Mpris2Proxy::builder(&conn)
+                        .destination(x.to_string())?
+                        .build()
+                        .await
+                        .map(|x| Box::new(x) as Box<dyn MusicCtl>),
+                    PluginKind::Mpd => Err(zbus::Error::Unsupported),
                 }
-            }),
-    )
-    .await?;
+            })
+            .await
+            .map_err(|_| McError::Timeout)
+            .and_then(|proxy| {
+                Ok(Box::new(timed::Timed::new(proxy?, x, timeout)) as Box<dyn MusicCtl>)
+            });
+            (x, proxy)
+        }
+    }))
+    .await
+    .into_iter()
+    .filter_map(|(x, proxy)| match proxy {
+        Ok(proxy) => Some(proxy),
+        Err(e) => {
+            debug!("Skipping {x}: {e}");
+            None
+        }
+    })
+    .collect();
 
-    if xs.contains(&radiotray::RADIOTRAY_NG.to_string()) {
-        let x = radiotray::RadioTrayNGProxy::builder(conn).build().await?;
-        list.push(Box::new(x));
+    if let Some(host) = mpd_host {
+        trace!("Connecting to MPD at {host}");
+        match tokio::time::timeout(timeout, mpd::Mpd::connect(host)).await {
+            Ok(Ok(x)) => list.push(Box::new(timed::Timed::new(Box::new(x), host, timeout))),
+            Ok(Err(e)) => debug!("Skipping MPD at {host}: {e}"),
+            Err(_) => debug!("Skipping MPD at {host}: {}", McError::Timeout),
+        }
+        list.sort_by_key(|x| x.mc_kind() == PluginKind::RadioTrayNG);
     }
 
     Ok(list)
 }
+
+/// How long a player may take to answer the liveness probe in `prune`
+const PRUNE_TIMEOUT: Duration = Duration::from_millis(500);
+
+fn is_stale(e: &zbus::Error) -> bool {
+    match e {
+        zbus::Error::MethodError(name, _, _) => matches!(
+            name.as_str(),
+            "org.freedesktop.DBus.Error.ServiceUnknown" | "org.freedesktop.DBus.Error.NoReply"
+        ),
+        zbus::Error::FDO(e) => {
+            matches!(**e, fdo::Error::ServiceUnknown(_) | fdo::Error::NoReply(_))
+        }
+        _ => false,
+    }
+}
+
+/// Drop players whose bus name is left over from a crashed process
+pub async fn prune(list: Vec<Box<dyn MusicCtl>>) -> Vec<Box<dyn MusicCtl>> {
+    let alive = join_all(list.iter().map(|x| async move {
+        match tokio::time::timeout(PRUNE_TIMEOUT, x.mc_canplay()).await {
+            Ok(Err(McError::Zbus(e))) => !is_stale(&e),
+            Ok(Err(McError::Timeout)) => false,
+            Ok(_) => true,
+            Err(_) => false,
+        }
+    }))
+    .await;
+
+    list.into_iter()
+        .zip(alive)
+        .filter_map(|(x, alive)| alive.then_some(x))
+        .collect()
+}
+
+/// Metadata keys of which at least one identifies a playable track
+const TRACK_KEYS: [&str; 3] = ["xesam:title", "xesam:artist", "xesam:url"];
+
+/// Whether the player behind `proxy` has a track to play, `allowed` being
+/// the value of its `property` (CanPlay or equivalent)
+pub(crate) async fn has_track(
+    proxy: &zbus::Proxy<'_>,
+    allowed: bool,
+    property: &str,
+) -> Result<bool, McError> {
+    let dest = proxy.destination();
+    if !allowed {
+        debug!("{dest}: {property}=false");
+        return Ok(false);
+    }
+    let xs = get_metadata(proxy).await?;
+    if !TRACK_KEYS.iter().any(|x| xs.contains_key(*x)) {
+        debug!("{dest}: metadata missing xesam:title, xesam:artist and xesam:url");
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+/// Whether `player` can play, a player that doesn't answer can't; when
+/// `strict` the track must also have an artist
+async fn can_play(player: &dyn MusicCtl, strict: bool) -> Result<bool, McError> {
+    let found = match player.mc_canplay().await {
+        Err(McError::Timeout) => false,
+        x => x?,
+    };
+    if !found {
+        debug!("Skipping {}: cannot play", player.mc_busname().await?);
+        return Ok(false);
+    }
+    if strict && player.mc_info().await?.is_none_or(|x| x.artist.is_empty()) {
+        debug!(
+            "Skipping {}: track has no artist",
+            player.mc_busname().await?
+        );
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+/// Player able to play, optionally restricted to names containing `name`
+/// (ignoring case)
+///
+/// Without `name` a player matching an earlier `priority` entry wins, then
+/// one that is playing, then one that is paused. With `name` an exact name
+/// match wins over a substring match, then a playing player wins over the
+/// rest. With `strict` only players whose track has an artist are used.
+pub async fn first_active<'a>(
+    name: &'a Option<String>,
+    priority: &[String],
+    strict: bool,
+    list: &'a [Box<dyn MusicCtl>],
+) -> Result<&'a Box<dyn MusicCtl>, McError> {
+    let Some(name) = name else {
+        let mut best = None;
+        for item in list {
+            if !can_play(item.as_ref(), strict).await? {
+                continue;
+            }
+            let item_name = item.mc_name().await?.to_lowercase();
+            let busname = item.mc_busname().await?.to_lowercase();
+            let preferred = priority
+                .iter()
+                .map(|x| x.to_lowercase())
+                .position(|x| item_name.contains(&x) || busname.contains(&x))
+                .unwrap_or(priority.len());
+            let status = match item.mc_status().await? {
+                PlaybackStatus::Playing => 0,
+                PlaybackStatus::Paused => 1,
+                PlaybackStatus::Stopped => 2,
+            };
+            let rank = (preferred, status);
+            if best.as_ref().is_none_or(|(x, _)| rank < *x) {
+                best = Some((rank, item));
+            }
+        }
+        return best.map(|(_, x)| x).ok_or(McError::NoActive);
+    };
+    let name = name.to_lowercase();
+    let mut best = None;
+    for item in list {
+        if !can_play(item.as_ref(), strict).await? {
+            continue;
+        }
+        let item_name = item.mc_name().await?.to_lowercase();
+        if !item_name.contains(&name) {
+            debug!("Skipping {item_name}: does not match {name}");
+            continue;
+        }
+        let playing = item.mc_status().await? == PlaybackStatus::Playing;
+        let rank = (item_name != name, !playing);
+        if best.as_ref().is_none_or(|(x, _)| rank < *x) {
+            best = Some((rank, item));
+        }
+    }
+    best.map(|(_, x)| x).ok_or(McError::NoActive)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dict() -> Value<'static> {
+        Value::from(HashMap::from([(
+            "xesam:title".to_string(),
+            Value::from("Song"),
+        )]))
+    }
+
+    fn title(xs: &HashMap<String, Value<'_>>) -> String {
+        variant_val_to_string(&xs["xesam:title"])
+    }
+
+    #[test]
+    fn format_duration_minutes() {
+        assert_eq!(format_duration(0), "0:00");
+        assert_eq!(format_duration(83_999_999), "1:23");
+    }
+
+    #[test]
+    fn format_duration_hours() {
+        assert_eq!(format_duration(3_723_000_000), "1:02:03");
+    }
+
+    #[test]
+    fn unwrap_metadata_plain_dict() {
+        assert_eq!(title(&unwrap_metadata(dict()).unwrap()), "Song");
+    }
+
+    #[test]
+    fn unwrap_metadata_wrapped_dict() {
+        let x = Value::Value(Box::new(dict()));
+        assert_eq!(title(&unwrap_metadata(x).unwrap()), "Song");
+    }
+
+    #[test]
+    fn unwrap_metadata_wrapped_twice() {
+        let x = Value::Value(Box::new(Value::Value(Box::new(dict()))));
+        assert_eq!(title(&unwrap_metadata(x).unwrap()), "Song");
+    }
+
+    #[test]
+    fn unwrap_metadata_not_a_dict() {
+        let x = Value::Value(Box::new(Value::from("Song")));
+        assert!(matches!(
+            unwrap_metadata(x),
+            Err(McError::Zbus(zbus::Error::Variant(
+                zvariant::Error::IncorrectType
+            )))
+        ));
+    }
+}
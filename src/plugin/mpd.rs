@@ -0,0 +1,225 @@
+// Copyright 2024 (c) Nathaniel Clark
+
+use crate::plugin::{
+    Capabilities, LoopStatus, McError, MusicCtl, MusicInfo, PlaybackStatus, PluginKind,
+};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+use tracing::debug;
+
+const DEFAULT_PORT: u16 = 6600;
+
+/// Response of an MPD command as `key: value` pairs
+type Response = Vec<(String, String)>;
+
+/// MPD server spoken to over its native protocol
+pub struct Mpd {
+    addr: String,
+}
+
+fn field<'a>(xs: &'a Response, key: &str) -> Option<&'a str> {
+    xs.iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(key))
+        .map(|(_, v)| v.as_str())
+}
+
+/// Track or disc number, which may be given as `n/total`
+fn number(x: &str) -> Option<u32> {
+    x.split('/').next()?.trim().parse().ok()
+}
+
+fn seconds_to_micros(x: &str) -> Option<u64> {
+    x.parse::<f64>().ok().map(|x| (x * 1_000_000.0) as u64)
+}
+
+impl Mpd {
+    /// Connect to the MPD server at `host` (`host` or `host:port`)
+    pub async fn connect(host: &str) -> Result<Self, McError> {
+        let addr = match host.contains(':') {
+            true => host.to_string(),
+            false => format!("{host}:{DEFAULT_PORT}"),
+        };
+        let mpd = Mpd { addr };
+        mpd.request("ping").await?;
+        Ok(mpd)
+    }
+
+    /// Send `command` on a new connection and collect the response
+    async fn request(&self, command: &str) -> Result<Response, McError> {
+        let mut stream = TcpStream::connect(&self.addr).await?;
+        let (read, mut write) = stream.split();
+        let mut lines = BufReader::new(read).lines();
+        match lines.next_line().await? {
+            Some(x) if x.starts_with("OK MPD") => {}
+            _ => return Err(McError::Mpd("not an MPD server".to_string())),
+        }
+        write.write_all(format!("{command}\n").as_bytes()).await?;
+
+        let mut out = Vec::new();
+        while let Some(line) = lines.next_line().await? {
+            if line == "OK" {
+                return Ok(out);
+            }
+            if let Some(e) = line.strip_prefix("ACK ") {
+                return Err(McError::Mpd(e.to_string()));
+            }
+            if let Some((k, v)) = line.split_once(": ") {
+                out.push((k.to_string(), v.to_string()));
+            }
+        }
+        Err(McError::Mpd("connection closed".to_string()))
+    }
+
+    async fn command(&self, command: &str) -> Result<(), McError> {
+        self.request(command).await?;
+        Ok(())
+    }
+
+    async fn status(&self) -> Result<Response, McError> {
+        self.request("status").await
+    }
+}
+
+#[async_trait]
+impl MusicCtl for Mpd {
+    async fn mc_play(&self) -> Result<(), McError> {
+        self.command("play").await
+    }
+    async fn mc_pause(&self) -> Result<(), McError> {
+        self.command("pause 1").await
+    }
+    async fn mc_toggle(&self) -> Result<(), McError> {
+        match self.mc_status().await? {
+            PlaybackStatus::Playing => self.command("pause 1").await,
+            _ => self.command("play").await,
+        }
+    }
+    async fn mc_stop(&self) -> Result<(), McError> {
+        self.command("stop").await
+    }
+    async fn mc_name(&self) -> Result<String, McError> {
+        Ok("MPD".to_string())
+    }
+    fn mc_kind(&self) -> PluginKind {
+        PluginKind::Mpd
+    }
+    async fn mc_busname(&self) -> Result<String, McError> {
+        Ok(self.addr.clone())
+    }
+    async fn mc_info(&self) -> Result<Option<MusicInfo>, McError> {
+        let xs = self.request("currentsong").await?;
+        if xs.is_empty() {
+            return Ok(None);
+        }
+        let tag = |key| field(&xs, key).unwrap_or_default().to_string();
+        Ok(Some(MusicInfo {
+            artist: tag("Artist"),
+            title: match field(&xs, "Title") {
+                Some(x) => x.to_string(),
+                None => tag("file"),
+            },
+            album: tag("Album"),
+            cover: String::new(),
+            genre: tag("Genre"),
+            year: field(&xs, "Date").and_then(|x| x.get(..4)?.parse().ok()),
+            track_number: field(&xs, "Track").and_then(number),
+            disc_number: field(&xs, "Disc").and_then(number),
+            bpm: 0,
+            length: field(&xs, "duration")
+                .or(field(&xs, "Time"))
+                .and_then(seconds_to_micros)
+                .unwrap_or_default(),
+            position: None,
+        }))
+    }
+    async fn mc_raw_metadata(&self) -> Result<HashMap<String, String>, McError> {
+        Ok(self.request("currentsong").await?.into_iter().collect())
+    }
+    async fn mc_next(&self) -> Result<(), McError> {
+        self.command("next").await
+    }
+    async fn mc_prev(&self) -> Result<(), McError> {
+        self.command("previous").await
+    }
+    async fn mc_seek(&self, offset_micros: i64) -> Result<(), McError> {
+        let offset = offset_micros as f64 / 1_000_000.0;
+        self.command(&format!("seekcur {offset:+}")).await
+    }
+    async fn mc_restart(&self) -> Result<(), McError> {
+        self.command("seekcur 0").await
+    }
+    async fn mc_get_volume(&self) -> Result<f64, McError> {
+        match field(&self.status().await?, "volume").and_then(|x| x.parse::<f64>().ok()) {
+            Some(x) if x >= 0.0 => Ok(x / 100.0),
+            _ => Err(McError::Unsupported("volume")),
+        }
+    }
+    async fn mc_set_volume(&self, v: f64) -> Result<(), McError> {
+        self.command(&format!("setvol {}", (v * 100.0).round() as u8))
+            .await
+    }
+    async fn mc_canplay(&self) -> Result<bool, McError> {
+        let found = !self.request("currentsong").await?.is_empty();
+        if !found {
+            debug!("{}: no current song", self.addr);
+        }
+        Ok(found)
+    }
+    async fn mc_status(&self) -> Result<PlaybackStatus, McError> {
+        Ok(match field(&self.status().await?, "state") {
+            Some("play") => PlaybackStatus::Playing,
+            Some("pause") => PlaybackStatus::Paused,
+            _ => PlaybackStatus::Stopped,
+        })
+    }
+    async fn mc_get_loop(&self) -> Result<LoopStatus, McError> {
+        let xs = self.status().await?;
+        Ok(match (field(&xs, "repeat"), field(&xs, "single")) {
+            (Some("1"), Some("1")) => LoopStatus::Track,
+            (Some("1"), _) => LoopStatus::Playlist,
+            _ => LoopStatus::None,
+        })
+    }
+    async fn mc_set_loop(&self, status: LoopStatus) -> Result<(), McError> {
+        let (repeat, single) = match status {
+            LoopStatus::None => (0, 0),
+            LoopStatus::Track => (1, 1),
+            LoopStatus::Playlist => (1, 0),
+        };
+        self.command(&format!(
+            "command_list_begin\nrepeat {repeat}\nsingle {single}\ncommand_list_end"
+        ))
+        .await
+    }
+    async fn mc_get_shuffle(&self) -> Result<bool, McError> {
+        Ok(field(&self.status().await?, "random") == Some("1"))
+    }
+    async fn mc_set_shuffle(&self, on: bool) -> Result<(), McError> {
+        self.command(&format!("random {}", u8::from(on))).await
+    }
+    async fn mc_position(&self) -> Result<u64, McError> {
+        Ok(field(&self.status().await?, "elapsed")
+            .and_then(seconds_to_micros)
+            .unwrap_or_default())
+    }
+    async fn mc_queue_pos(&self) -> Result<Option<(usize, usize)>, McError> {
+        let xs = self.status().await?;
+        let song = field(&xs, "song").and_then(|x| x.parse::<usize>().ok());
+        let total = field(&xs, "playlistlength").and_then(|x| x.parse::<usize>().ok());
+        Ok(song.zip(total).map(|(n, total)| (n + 1, total)))
+    }
+    async fn mc_capabilities(&self) -> Result<Capabilities, McError> {
+        Ok(Capabilities {
+            play: true,
+            pause: true,
+            next: true,
+            prev: true,
+            seek: true,
+            control: true,
+        })
+    }
+}
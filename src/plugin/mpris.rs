@@ -1,61 +1,292 @@
 // Copyright 2023 (c) Nathaniel Clark
 
-use crate::plugin::{McError, MusicCtl, MusicInfo};
+use crate::plugin::{
+    get_metadata, has_track, variant_list_to_string, Capabilities, LoopStatus, McError, MusicCtl,
+    MusicInfo, PlaybackStatus, PluginKind,
+};
 use async_trait::async_trait;
+use futures::{
+    stream::{self, BoxStream},
+    StreamExt,
+};
 use std::collections::HashMap;
+use zbus::CacheProperties;
 use zbus_macros::proxy;
-use zvariant::Value;
+use zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value};
 
 pub(crate) const MPRIS_PREFIX: &str = "org.mpris.MediaPlayer2.";
+const SPOTIFY: &str = "org.mpris.MediaPlayer2.spotify";
 
 #[proxy(
     interface = "org.mpris.MediaPlayer2.Player",
     default_service = "org.mpris.MediaPlayer2",
     default_path = "/org/mpris/MediaPlayer2"
 )]
-trait Mpris2 {
+pub trait Mpris2 {
+    fn play(&self) -> zbus::Result<()>;
+    fn pause(&self) -> zbus::Result<()>;
     fn play_pause(&self) -> zbus::Result<()>;
     fn next(&self) -> zbus::Result<()>;
     fn previous(&self) -> zbus::Result<()>;
     fn stop(&self) -> zbus::Result<()>;
+    fn seek(&self, offset: i64) -> zbus::Result<()>;
+    fn set_position(&self, track_id: &ObjectPath<'_>, position: i64) -> zbus::Result<()>;
+    #[zbus(property)]
+    fn playback_status(&self) -> zbus::Result<String>;
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn position(&self) -> zbus::Result<i64>;
+    #[zbus(property)]
+    fn loop_status(&self) -> zbus::Result<String>;
+    #[zbus(property)]
+    fn set_loop_status(&self, value: &str) -> zbus::Result<()>;
+    #[zbus(property)]
+    fn shuffle(&self) -> zbus::Result<bool>;
+    #[zbus(property)]
+    fn set_shuffle(&self, value: bool) -> zbus::Result<()>;
     #[zbus(property)]
     fn can_play(&self) -> zbus::Result<bool>;
-    // returns xml
     #[zbus(property)]
-    fn metadata(&self) -> zbus::Result<HashMap<String, Value>>;
+    fn can_pause(&self) -> zbus::Result<bool>;
+    #[zbus(property)]
+    fn can_go_next(&self) -> zbus::Result<bool>;
+    #[zbus(property)]
+    fn can_go_previous(&self) -> zbus::Result<bool>;
+    #[zbus(property)]
+    fn can_seek(&self) -> zbus::Result<bool>;
+    #[zbus(property)]
+    fn can_control(&self) -> zbus::Result<bool>;
+    #[zbus(property)]
+    fn volume(&self) -> zbus::Result<f64>;
+    #[zbus(property)]
+    fn set_volume(&self, value: f64) -> zbus::Result<()>;
+    // a{sv} keyed by xesam:/mpris: names
+    #[zbus(property)]
+    fn metadata(&self) -> zbus::Result<HashMap<String, Value<'_>>>;
+}
+
+#[proxy(
+    interface = "org.mpris.MediaPlayer2",
+    default_service = "org.mpris.MediaPlayer2",
+    default_path = "/org/mpris/MediaPlayer2"
+)]
+pub trait MediaPlayer2 {
+    fn raise(&self) -> zbus::Result<()>;
+    fn quit(&self) -> zbus::Result<()>;
+    #[zbus(property)]
+    fn can_quit(&self) -> zbus::Result<bool>;
+    #[zbus(property)]
+    fn identity(&self) -> zbus::Result<String>;
+}
+
+#[proxy(
+    interface = "org.mpris.MediaPlayer2.TrackList",
+    default_service = "org.mpris.MediaPlayer2",
+    default_path = "/org/mpris/MediaPlayer2"
+)]
+pub trait TrackList {
+    #[zbus(property)]
+    fn tracks(&self) -> zbus::Result<Vec<OwnedObjectPath>>;
+}
+
+/// Fail with `Unsupported(action)` unless the player allows it
+fn require(allowed: bool, action: &'static str) -> Result<(), McError> {
+    if allowed {
+        Ok(())
+    } else {
+        Err(McError::Unsupported(action))
+    }
+}
+
+fn trackid<'a>(xs: &'a HashMap<String, Value>) -> Option<ObjectPath<'a>> {
+    match xs.get("mpris:trackid")? {
+        Value::ObjectPath(x) => Some(x.clone()),
+        Value::Str(x) => ObjectPath::try_from(x.as_str()).ok(),
+        _ => None,
+    }
+}
+
+impl Mpris2Proxy<'_> {
+    /// Proxy for the root interface of the same player
+    async fn root(&self) -> zbus::Result<MediaPlayer2Proxy<'_>> {
+        MediaPlayer2Proxy::builder(self.inner().connection())
+            .destination(self.inner().destination().to_owned())?
+            .cache_properties(CacheProperties::No)
+            .build()
+            .await
+    }
 }
 
 #[async_trait]
-impl MusicCtl for Mpris2Proxy<'_> {
+impl MusicCtl for Mpris2Proxy<'static> {
     async fn mc_play(&self) -> Result<(), McError> {
+        require(self.can_play().await?, "play")?;
+        self.play().await?;
+        Ok(())
+    }
+    async fn mc_pause(&self) -> Result<(), McError> {
+        require(self.can_pause().await?, "pause")?;
+        self.pause().await?;
+        Ok(())
+    }
+    async fn mc_toggle(&self) -> Result<(), McError> {
+        require(self.can_pause().await?, "toggle")?;
         self.play_pause().await?;
         Ok(())
     }
     async fn mc_stop(&self) -> Result<(), McError> {
+        require(self.can_control().await?, "stop")?;
         self.stop().await?;
         Ok(())
     }
     async fn mc_name(&self) -> Result<String, McError> {
-        let name = &self.inner().destination().as_str()["org.mpris.MediaPlayer2.".len()..];
-        Ok(format!("{name} (MPRIS)"))
+        if self.inner().destination().as_str() == SPOTIFY {
+            return Ok("Spotify".to_string());
+        }
+        match self.root().await?.identity().await {
+            Ok(name) if !name.is_empty() => Ok(name),
+            _ => {
+                let dest = self.inner().destination().as_str();
+                Ok(dest.strip_prefix(MPRIS_PREFIX).unwrap_or(dest).to_string())
+            }
+        }
+    }
+    fn mc_kind(&self) -> PluginKind {
+        PluginKind::Mpris
+    }
+    async fn mc_busname(&self) -> Result<String, McError> {
+        Ok(self.inner().destination().to_string())
     }
     async fn mc_info(&self) -> Result<Option<MusicInfo>, McError> {
-        let xs = self.metadata().await?;
+        let xs = get_metadata(self.inner()).await?;
         if xs.is_empty() {
             Ok(None)
         } else {
             Ok(Some(xs.try_into()?))
         }
     }
+    async fn mc_raw_metadata(&self) -> Result<HashMap<String, String>, McError> {
+        Ok(get_metadata(self.inner())
+            .await?
+            .iter()
+            .map(|(k, v)| (k.clone(), variant_list_to_string(v)))
+            .collect())
+    }
     async fn mc_next(&self) -> Result<(), McError> {
+        require(self.can_go_next().await?, "next")?;
         self.next().await?;
         Ok(())
     }
     async fn mc_prev(&self) -> Result<(), McError> {
+        require(self.can_go_previous().await?, "prev")?;
         self.previous().await?;
         Ok(())
     }
+    async fn mc_seek(&self, offset_micros: i64) -> Result<(), McError> {
+        require(self.can_seek().await?, "seek")?;
+        self.seek(offset_micros).await?;
+        Ok(())
+    }
+    async fn mc_restart(&self) -> Result<(), McError> {
+        require(self.can_seek().await?, "restart")?;
+        let xs = get_metadata(self.inner()).await?;
+        let trackid = trackid(&xs).ok_or(McError::Unsupported("restart"))?;
+        self.set_position(&trackid, 0).await?;
+        Ok(())
+    }
+    async fn mc_raw(&self, method: &str) -> Result<(), McError> {
+        match method {
+            "Play" => self.play().await?,
+            "Pause" => self.pause().await?,
+            "PlayPause" => self.play_pause().await?,
+            "Stop" => self.stop().await?,
+            "Next" => self.next().await?,
+            "Previous" => self.previous().await?,
+            _ => return Err(McError::UnknownMethod(method.to_string())),
+        }
+        Ok(())
+    }
+    async fn mc_get_volume(&self) -> Result<f64, McError> {
+        Ok(self.volume().await?)
+    }
+    async fn mc_set_volume(&self, v: f64) -> Result<(), McError> {
+        self.set_volume(v).await?;
+        Ok(())
+    }
+    async fn mc_status(&self) -> Result<PlaybackStatus, McError> {
+        Ok(match self.playback_status().await?.as_str() {
+            "Playing" => PlaybackStatus::Playing,
+            "Paused" => PlaybackStatus::Paused,
+            _ => PlaybackStatus::Stopped,
+        })
+    }
+    async fn mc_get_loop(&self) -> Result<LoopStatus, McError> {
+        LoopStatus::from_mpris(&self.loop_status().await?).ok_or(McError::Unsupported("loop"))
+    }
+    async fn mc_set_loop(&self, status: LoopStatus) -> Result<(), McError> {
+        self.set_loop_status(status.as_mpris()).await?;
+        Ok(())
+    }
+    async fn mc_get_shuffle(&self) -> Result<bool, McError> {
+        Ok(self.shuffle().await?)
+    }
+    async fn mc_set_shuffle(&self, on: bool) -> Result<(), McError> {
+        self.set_shuffle(on).await?;
+        Ok(())
+    }
+    async fn mc_position(&self) -> Result<u64, McError> {
+        Ok(self.position().await?.max(0) as u64)
+    }
     async fn mc_canplay(&self) -> Result<bool, McError> {
-        Ok(self.can_play().await? && self.metadata().await?.contains_key("xesam:artist"))
+        has_track(self.inner(), self.can_play().await?, "CanPlay").await
+    }
+    async fn mc_queue_pos(&self) -> Result<Option<(usize, usize)>, McError> {
+        let xs = get_metadata(self.inner()).await?;
+        let Some(trackid) = trackid(&xs) else {
+            return Ok(None);
+        };
+        let proxy = TrackListProxy::builder(self.inner().connection())
+            .destination(self.inner().destination().to_owned())?
+            .build()
+            .await?;
+        // Players without a TrackList interface fail here
+        let Ok(tracks) = proxy.tracks().await else {
+            return Ok(None);
+        };
+        Ok(tracks
+            .iter()
+            .position(|x| x.as_ref() == trackid)
+            .map(|i| (i + 1, tracks.len())))
+    }
+    async fn mc_raise(&self) -> Result<(), McError> {
+        self.root().await?.raise().await?;
+        Ok(())
+    }
+    async fn mc_quit(&self) -> Result<(), McError> {
+        let root = self.root().await?;
+        require(root.can_quit().await?, "quit")?;
+        root.quit().await?;
+        Ok(())
+    }
+    async fn mc_capabilities(&self) -> Result<Capabilities, McError> {
+        Ok(Capabilities {
+            play: self.can_play().await?,
+            pause: self.can_pause().await?,
+            next: self.can_go_next().await?,
+            prev: self.can_go_previous().await?,
+            seek: self.can_seek().await?,
+            control: self.can_control().await?,
+        })
+    }
+    async fn mc_changes(&self) -> Result<BoxStream<'static, ()>, McError> {
+        // Streams of this proxy fire after its property cache is updated,
+        // the clone shares that cache
+        let proxy = self.inner().clone();
+        let metadata = proxy
+            .receive_property_changed::<OwnedValue>("Metadata")
+            .await;
+        let status = proxy
+            .receive_property_changed::<OwnedValue>("PlaybackStatus")
+            .await;
+        Ok(stream::select(metadata, status).map(|_| ()).boxed())
     }
 }
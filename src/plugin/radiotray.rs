@@ -1,8 +1,12 @@
 // Copyright 2023 (c) Nathaniel Clark
 
-use crate::plugin::{get_json_string, McError, MusicCtl, MusicInfo};
+use crate::plugin::{
+    get_json_string, Capabilities, McError, MusicCtl, MusicInfo, PlaybackStatus, PluginKind,
+};
 use async_trait::async_trait;
 use serde_json::Value;
+use std::collections::HashMap;
+use tracing::debug;
 use zbus_macros::proxy;
 
 pub(crate) const RADIOTRAY_NG: &str = "com.github.radiotray_ng";
@@ -12,7 +16,7 @@ pub(crate) const RADIOTRAY_NG: &str = "com.github.radiotray_ng";
     default_service = "com.github.radiotray_ng",
     default_path = "/com/github/radiotray_ng"
 )]
-trait RadioTrayNG {
+pub trait RadioTrayNG {
     #[zbus(name = "play")]
     fn play(&self) -> zbus::Result<()>;
     #[zbus(name = "mute")]
@@ -26,6 +30,9 @@ trait RadioTrayNG {
     // returns quoted json
     #[zbus(name = "get_player_state")]
     fn get_player_state(&self) -> zbus::Result<String>;
+    // returns quoted json
+    #[zbus(name = "get_bookmarks")]
+    fn get_bookmarks(&self) -> zbus::Result<String>;
 }
 
 #[async_trait]
@@ -34,6 +41,18 @@ impl MusicCtl for RadioTrayNGProxy<'_> {
         self.play().await?;
         Ok(())
     }
+    async fn mc_pause(&self) -> Result<(), McError> {
+        self.stop().await?;
+        Ok(())
+    }
+    async fn mc_toggle(&self) -> Result<(), McError> {
+        let xs: Value = serde_json::from_str(self.get_player_state().await?.as_str())?;
+        match get_json_string(&xs, "state").as_str() {
+            "playing" | "buffering" => self.stop().await?,
+            _ => self.play().await?,
+        }
+        Ok(())
+    }
     async fn mc_stop(&self) -> Result<(), McError> {
         self.stop().await?;
         Ok(())
@@ -41,6 +60,12 @@ impl MusicCtl for RadioTrayNGProxy<'_> {
     async fn mc_name(&self) -> Result<String, McError> {
         Ok("RadioTrayNG".to_string())
     }
+    fn mc_kind(&self) -> PluginKind {
+        PluginKind::RadioTrayNG
+    }
+    async fn mc_busname(&self) -> Result<String, McError> {
+        Ok(self.inner().destination().to_string())
+    }
     async fn mc_info(&self) -> Result<Option<MusicInfo>, McError> {
         let xs: Value = serde_json::from_str(self.get_player_state().await?.as_str())?;
 
@@ -52,9 +77,28 @@ impl MusicCtl for RadioTrayNGProxy<'_> {
                 title: get_json_string(&xs, "title"),
                 album: get_json_string(&xs, "station"),
                 cover: "".to_string(),
+                genre: "".to_string(),
+                year: None,
+                track_number: None,
+                disc_number: None,
+                bpm: 0,
+                length: 0,
+                position: None,
             }))
         }
     }
+    async fn mc_raw_metadata(&self) -> Result<HashMap<String, String>, McError> {
+        let xs: Value = serde_json::from_str(self.get_player_state().await?.as_str())?;
+        Ok(xs
+            .as_object()
+            .into_iter()
+            .flatten()
+            .map(|(k, v)| match v {
+                Value::String(x) => (k.clone(), x.clone()),
+                x => (k.clone(), x.to_string()),
+            })
+            .collect())
+    }
     async fn mc_next(&self) -> Result<(), McError> {
         self.next_station().await?;
         Ok(())
@@ -63,13 +107,57 @@ impl MusicCtl for RadioTrayNGProxy<'_> {
         self.previous_station().await?;
         Ok(())
     }
+    async fn mc_stations(&self) -> Result<Vec<(String, Vec<String>)>, McError> {
+        let xs: Value = serde_json::from_str(self.get_bookmarks().await?.as_str())?;
+
+        Ok(xs
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|group| {
+                let stations = group
+                    .get("stations")
+                    .and_then(|x| x.as_array())
+                    .into_iter()
+                    .flatten()
+                    .map(|x| get_json_string(x, "name"))
+                    .collect();
+                (get_json_string(group, "group"), stations)
+            })
+            .collect())
+    }
+    async fn mc_status(&self) -> Result<PlaybackStatus, McError> {
+        let xs: Value = serde_json::from_str(self.get_player_state().await?.as_str())?;
+        Ok(match get_json_string(&xs, "state").as_str() {
+            "playing" | "buffering" => PlaybackStatus::Playing,
+            "paused" => PlaybackStatus::Paused,
+            _ => PlaybackStatus::Stopped,
+        })
+    }
     async fn mc_canplay(&self) -> Result<bool, McError> {
-        Ok(
-            serde_json::from_str::<Value>(self.get_player_state().await?.as_str())?
-                .get("url")
-                .and_then(|x| x.as_str())
-                .map(|x| !x.is_empty())
-                .unwrap_or_default(),
-        )
+        let found = serde_json::from_str::<Value>(self.get_player_state().await?.as_str())?
+            .get("url")
+            .and_then(|x| x.as_str())
+            .map(|x| !x.is_empty())
+            .unwrap_or_default();
+        if !found {
+            debug!("{}: no station url", self.inner().destination());
+        }
+        Ok(found)
+    }
+    async fn mc_mute(&self) -> Result<(), McError> {
+        self.mute().await?;
+        Ok(())
+    }
+    async fn mc_capabilities(&self) -> Result<Capabilities, McError> {
+        // pause stops, next and prev change station
+        Ok(Capabilities {
+            play: true,
+            pause: true,
+            next: true,
+            prev: true,
+            seek: false,
+            control: true,
+        })
     }
 }
@@ -1,33 +1,77 @@
 // Copyright 2024 (c) Nathaniel Clark
 
-use crate::plugin::{McError, MusicCtl, MusicInfo};
+use crate::plugin::{
+    get_metadata, has_track, mpris::MPRIS_PREFIX, variant_list_to_string, Capabilities, McError,
+    MusicCtl, MusicInfo, PlaybackStatus, PluginKind,
+};
 use async_trait::async_trait;
+use futures::{
+    stream::{self, BoxStream},
+    StreamExt,
+};
 use std::collections::HashMap;
 use zbus_macros::proxy;
-use zvariant::Value;
+use zvariant::{OwnedValue, Value};
 
 pub const SERVICE_NAME: &str = "org.mpris.MediaPlayer2.ShairportSync";
 
+/// AirPlay volume in dB meaning mute
+const AIRPLAY_MUTE: f64 = -144.0;
+/// AirPlay volume in dB of the lowest audible setting
+const AIRPLAY_MIN: f64 = -30.0;
+
+/// Map volume (0.0 to 1.0) onto the AirPlay scale: 0 is mute (-144dB),
+/// anything else is spread over -30dB to 0dB
+fn volume_to_airplay(v: f64) -> f64 {
+    if v <= 0.0 {
+        AIRPLAY_MUTE
+    } else {
+        AIRPLAY_MIN * (1.0 - v.min(1.0))
+    }
+}
+
+/// Inverse of `volume_to_airplay`, -30dB and below (including mute) is 0
+fn airplay_to_volume(db: f64) -> f64 {
+    if db <= AIRPLAY_MIN {
+        0.0
+    } else {
+        1.0 - db.min(0.0) / AIRPLAY_MIN
+    }
+}
+
 #[proxy(
     interface = "org.gnome.ShairportSync.RemoteControl",
     default_service = "org.mpris.MediaPlayer2.ShairportSync",
     default_path = "/org/gnome/ShairportSync"
 )]
-trait ShairportSync {
+pub trait ShairportSync {
+    fn play(&self) -> zbus::Result<()>;
+    fn pause(&self) -> zbus::Result<()>;
     fn play_pause(&self) -> zbus::Result<()>;
     fn stop(&self) -> zbus::Result<()>;
     fn next(&self) -> zbus::Result<()>;
     fn previous(&self) -> zbus::Result<()>;
-    // returns xml
+    fn set_airplay_volume(&self, volume: f64) -> zbus::Result<()>;
+    #[zbus(property)]
+    fn airplay_volume(&self) -> zbus::Result<f64>;
+    // a{sv} keyed by xesam:/mpris: names, same as MPRIS
     #[zbus(property)]
-    fn metadata(&self) -> zbus::Result<HashMap<String, Value>>;
+    fn metadata(&self) -> zbus::Result<HashMap<String, Value<'_>>>;
     #[zbus(property)]
     fn available(&self) -> zbus::Result<bool>;
 }
 
 #[async_trait]
-impl MusicCtl for ShairportSyncProxy<'_> {
+impl MusicCtl for ShairportSyncProxy<'static> {
     async fn mc_play(&self) -> Result<(), McError> {
+        self.play().await?;
+        Ok(())
+    }
+    async fn mc_pause(&self) -> Result<(), McError> {
+        self.pause().await?;
+        Ok(())
+    }
+    async fn mc_toggle(&self) -> Result<(), McError> {
         self.play_pause().await?;
         Ok(())
     }
@@ -36,17 +80,31 @@ impl MusicCtl for ShairportSyncProxy<'_> {
         Ok(())
     }
     async fn mc_name(&self) -> Result<String, McError> {
-        let name = &self.inner().destination().as_str()["org.mpris.MediaPlayer2.".len()..];
+        let dest = self.inner().destination().as_str();
+        let name = dest.strip_prefix(MPRIS_PREFIX).unwrap_or(dest);
         Ok(name.to_string())
     }
+    fn mc_kind(&self) -> PluginKind {
+        PluginKind::ShairportSync
+    }
+    async fn mc_busname(&self) -> Result<String, McError> {
+        Ok(self.inner().destination().to_string())
+    }
     async fn mc_info(&self) -> Result<Option<MusicInfo>, McError> {
-        let xs = self.metadata().await?;
+        let xs = get_metadata(self.inner()).await?;
         if xs.is_empty() {
             Ok(None)
         } else {
             Ok(Some(xs.try_into()?))
         }
     }
+    async fn mc_raw_metadata(&self) -> Result<HashMap<String, String>, McError> {
+        Ok(get_metadata(self.inner())
+            .await?
+            .iter()
+            .map(|(k, v)| (k.clone(), variant_list_to_string(v)))
+            .collect())
+    }
     async fn mc_next(&self) -> Result<(), McError> {
         self.next().await?;
         Ok(())
@@ -55,7 +113,63 @@ impl MusicCtl for ShairportSyncProxy<'_> {
         self.previous().await?;
         Ok(())
     }
+    async fn mc_get_volume(&self) -> Result<f64, McError> {
+        Ok(airplay_to_volume(self.airplay_volume().await?))
+    }
+    async fn mc_set_volume(&self, v: f64) -> Result<(), McError> {
+        self.set_airplay_volume(volume_to_airplay(v)).await?;
+        Ok(())
+    }
+    async fn mc_status(&self) -> Result<PlaybackStatus, McError> {
+        if self.available().await? && !get_metadata(self.inner()).await?.is_empty() {
+            Ok(PlaybackStatus::Playing)
+        } else {
+            Ok(PlaybackStatus::Stopped)
+        }
+    }
     async fn mc_canplay(&self) -> Result<bool, McError> {
-        Ok(self.available().await? && self.metadata().await?.contains_key("xesam:artist"))
+        has_track(self.inner(), self.available().await?, "Available").await
+    }
+    async fn mc_capabilities(&self) -> Result<Capabilities, McError> {
+        Ok(Capabilities {
+            play: true,
+            pause: true,
+            next: true,
+            prev: true,
+            seek: false,
+            control: true,
+        })
+    }
+    async fn mc_changes(&self) -> Result<BoxStream<'static, ()>, McError> {
+        // Streams of this proxy fire after its property cache is updated,
+        // the clone shares that cache
+        let proxy = self.inner().clone();
+        let metadata = proxy
+            .receive_property_changed::<OwnedValue>("Metadata")
+            .await;
+        let available = proxy
+            .receive_property_changed::<OwnedValue>("Available")
+            .await;
+        Ok(stream::select(metadata, available).map(|_| ()).boxed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn volume_to_airplay_scale() {
+        assert_eq!(volume_to_airplay(0.0), -144.0);
+        assert_eq!(volume_to_airplay(1.0), 0.0);
+        assert_eq!(volume_to_airplay(0.5), -15.0);
+    }
+
+    #[test]
+    fn airplay_to_volume_scale() {
+        assert_eq!(airplay_to_volume(-144.0), 0.0);
+        assert_eq!(airplay_to_volume(-30.0), 0.0);
+        assert_eq!(airplay_to_volume(0.0), 1.0);
+        assert_eq!(airplay_to_volume(-15.0), 0.5);
     }
 }
@@ -0,0 +1,144 @@
+// Copyright 2024 (c) Nathaniel Clark
+
+use crate::plugin::{
+    Capabilities, LoopStatus, McError, MusicCtl, MusicInfo, PlaybackStatus, PluginKind,
+};
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+use std::{collections::HashMap, future::Future, time::Duration};
+use tracing::{debug, trace};
+
+/// Player whose calls fail with `McError::Timeout` instead of hanging
+pub(crate) struct Timed {
+    inner: Box<dyn MusicCtl>,
+    // bus name or address, for logging
+    name: String,
+    timeout: Duration,
+}
+
+impl Timed {
+    pub(crate) fn new(inner: Box<dyn MusicCtl>, name: &str, timeout: Duration) -> Self {
+        Self {
+            inner,
+            name: name.to_string(),
+            timeout,
+        }
+    }
+
+    async fn call<T>(
+        &self,
+        method: &str,
+        f: impl Future<Output = Result<T, McError>>,
+    ) -> Result<T, McError> {
+        trace!("{}: {method}", self.name);
+        let result = tokio::time::timeout(self.timeout, f)
+            .await
+            .map_err(|_| McError::Timeout)
+            .and_then(|x| x);
+        if let Err(e) = &result {
+            debug!("{}: {method} failed: {e}", self.name);
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl MusicCtl for Timed {
+    async fn mc_play(&self) -> Result<(), McError> {
+        self.call("mc_play", self.inner.mc_play()).await
+    }
+    async fn mc_pause(&self) -> Result<(), McError> {
+        self.call("mc_pause", self.inner.mc_pause()).await
+    }
+    async fn mc_toggle(&self) -> Result<(), McError> {
+        self.call("mc_toggle", self.inner.mc_toggle()).await
+    }
+    async fn mc_stop(&self) -> Result<(), McError> {
+        self.call("mc_stop", self.inner.mc_stop()).await
+    }
+    async fn mc_name(&self) -> Result<String, McError> {
+        self.call("mc_name", self.inner.mc_name()).await
+    }
+    fn mc_kind(&self) -> PluginKind {
+        self.inner.mc_kind()
+    }
+    async fn mc_busname(&self) -> Result<String, McError> {
+        self.call("mc_busname", self.inner.mc_busname()).await
+    }
+    async fn mc_info(&self) -> Result<Option<MusicInfo>, McError> {
+        self.call("mc_info", self.inner.mc_info()).await
+    }
+    async fn mc_raw_metadata(&self) -> Result<HashMap<String, String>, McError> {
+        self.call("mc_raw_metadata", self.inner.mc_raw_metadata())
+            .await
+    }
+    async fn mc_next(&self) -> Result<(), McError> {
+        self.call("mc_next", self.inner.mc_next()).await
+    }
+    async fn mc_prev(&self) -> Result<(), McError> {
+        self.call("mc_prev", self.inner.mc_prev()).await
+    }
+    async fn mc_seek(&self, offset_micros: i64) -> Result<(), McError> {
+        self.call("mc_seek", self.inner.mc_seek(offset_micros))
+            .await
+    }
+    async fn mc_restart(&self) -> Result<(), McError> {
+        self.call("mc_restart", self.inner.mc_restart()).await
+    }
+    async fn mc_raw(&self, method: &str) -> Result<(), McError> {
+        self.call("mc_raw", self.inner.mc_raw(method)).await
+    }
+    async fn mc_stations(&self) -> Result<Vec<(String, Vec<String>)>, McError> {
+        self.call("mc_stations", self.inner.mc_stations()).await
+    }
+    async fn mc_get_volume(&self) -> Result<f64, McError> {
+        self.call("mc_get_volume", self.inner.mc_get_volume()).await
+    }
+    async fn mc_set_volume(&self, v: f64) -> Result<(), McError> {
+        self.call("mc_set_volume", self.inner.mc_set_volume(v))
+            .await
+    }
+    async fn mc_canplay(&self) -> Result<bool, McError> {
+        self.call("mc_canplay", self.inner.mc_canplay()).await
+    }
+    async fn mc_status(&self) -> Result<PlaybackStatus, McError> {
+        self.call("mc_status", self.inner.mc_status()).await
+    }
+    async fn mc_get_loop(&self) -> Result<LoopStatus, McError> {
+        self.call("mc_get_loop", self.inner.mc_get_loop()).await
+    }
+    async fn mc_set_loop(&self, status: LoopStatus) -> Result<(), McError> {
+        self.call("mc_set_loop", self.inner.mc_set_loop(status))
+            .await
+    }
+    async fn mc_get_shuffle(&self) -> Result<bool, McError> {
+        self.call("mc_get_shuffle", self.inner.mc_get_shuffle())
+            .await
+    }
+    async fn mc_set_shuffle(&self, on: bool) -> Result<(), McError> {
+        self.call("mc_set_shuffle", self.inner.mc_set_shuffle(on))
+            .await
+    }
+    async fn mc_position(&self) -> Result<u64, McError> {
+        self.call("mc_position", self.inner.mc_position()).await
+    }
+    async fn mc_queue_pos(&self) -> Result<Option<(usize, usize)>, McError> {
+        self.call("mc_queue_pos", self.inner.mc_queue_pos()).await
+    }
+    async fn mc_mute(&self) -> Result<(), McError> {
+        self.call("mc_mute", self.inner.mc_mute()).await
+    }
+    async fn mc_raise(&self) -> Result<(), McError> {
+        self.call("mc_raise", self.inner.mc_raise()).await
+    }
+    async fn mc_quit(&self) -> Result<(), McError> {
+        self.call("mc_quit", self.inner.mc_quit()).await
+    }
+    async fn mc_capabilities(&self) -> Result<Capabilities, McError> {
+        self.call("mc_capabilities", self.inner.mc_capabilities())
+            .await
+    }
+    async fn mc_changes(&self) -> Result<BoxStream<'static, ()>, McError> {
+        self.call("mc_changes", self.inner.mc_changes()).await
+    }
+}
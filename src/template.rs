@@ -0,0 +1,59 @@
+// Copyright 2024 (c) Nathaniel Clark
+
+/// Substitute `{name}` placeholders in `template` from `fields`, unknown
+/// placeholders are left untouched
+pub(crate) fn render(template: &str, fields: &[(&str, &str)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest.find('}').and_then(|end| {
+            fields
+                .iter()
+                .find(|(name, _)| *name == &rest[1..end])
+                .map(|(_, value)| (end, value))
+        });
+        match value {
+            Some((end, value)) => {
+                out.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIELDS: &[(&str, &str)] = &[("artist", "Artist"), ("title", "Song")];
+
+    #[test]
+    fn render_fields() {
+        assert_eq!(render("{artist} - {title}", FIELDS), "Artist - Song");
+    }
+
+    #[test]
+    fn render_unknown_placeholder() {
+        assert_eq!(render("{title} {bogus}", FIELDS), "Song {bogus}");
+    }
+
+    #[test]
+    fn render_double_brace() {
+        assert_eq!(render("{{title}", FIELDS), "{Song");
+    }
+
+    #[test]
+    fn render_unterminated() {
+        assert_eq!(render("{title} {artist", FIELDS), "Song {artist");
+        assert_eq!(render("{", FIELDS), "{");
+    }
+}